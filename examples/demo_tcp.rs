@@ -10,8 +10,7 @@ async fn main() -> Result<(), RiemannClientError> {
         .metric_f(123.4)
         .build();
 
-    let response = client.send_events(vec![event]).await?;
-    println!("{:?}", response);
+    client.send_events(vec![event]).await?;
 
     let query_response = client.send_query("service = \"riemann_test\"").await?;
     println!("{:?}", query_response);
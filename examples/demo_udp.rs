@@ -4,7 +4,7 @@ use rustmann::{EventBuilder, RiemannClient, RiemannClientError, RiemannClientOpt
 async fn main() -> Result<(), RiemannClientError> {
     let options = RiemannClientOptionsBuilder::default()
         .host("localhost")
-        .port(5555 as u16)
+        .port(5555_u16)
         .use_udp(true)
         .build();
 
@@ -100,8 +100,8 @@ async fn main() -> Result<(), RiemannClientError> {
                 }
             }
 
-            let resp = client.send_events(vec![event_builder.build()]).await?;
-            println!("{:?}", resp);
+            client.send_events(vec![event_builder.build()]).await?;
+            println!("ok");
         }
     }
 
@@ -0,0 +1,321 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use derive_builder::Builder;
+use futures::Stream;
+use getset::Getters;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+
+use crate::client::RiemannClient;
+use crate::error::{BatchError, RiemannClientError};
+use crate::protos::riemann::Event;
+
+/// What to do with a new event when the batch queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the queue has room. `try_send` returns `BatchError::Full`
+    /// instead of waiting.
+    Block,
+    /// Discard the incoming event.
+    DropNewest,
+    /// Discard the oldest queued event to make room for the incoming one.
+    DropOldest,
+}
+
+/// Options for `BatchSender`
+#[derive(Builder, Clone, Debug, Getters)]
+#[builder(setter(into))]
+#[builder(build_fn(skip))]
+#[builder(pattern = "owned")]
+#[get = "pub"]
+pub struct BatchOptions {
+    /// Flush as soon as this many events are queued
+    max_batch_size: usize,
+    /// Flush when the oldest queued event has waited this long
+    max_delay_ms: u64,
+    /// Maximum number of events held in the queue
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+}
+
+impl BatchOptionsBuilder {
+    pub fn build(self) -> BatchOptions {
+        let max_batch_size = self.max_batch_size.unwrap_or(100).max(1);
+        BatchOptions {
+            max_batch_size,
+            max_delay_ms: self.max_delay_ms.unwrap_or(1000),
+            queue_capacity: self.queue_capacity.unwrap_or(10000).max(max_batch_size),
+            overflow_policy: self.overflow_policy.unwrap_or(OverflowPolicy::Block),
+        }
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptionsBuilder::default().build()
+    }
+}
+
+/// The outcome of one flush, delivered through `FlushReports`
+#[derive(Debug, Getters)]
+#[get = "pub"]
+pub struct FlushReport {
+    /// Number of events sent in this flush
+    events: usize,
+    /// Number of events discarded by the overflow policy since the previous
+    /// report
+    dropped: u64,
+    /// Result of the underlying `RiemannClient::send_events`
+    result: Result<(), RiemannClientError>,
+}
+
+impl FlushReport {
+    pub fn into_result(self) -> Result<(), RiemannClientError> {
+        self.result
+    }
+}
+
+/// Stream of `FlushReport`s from a `BatchSender`. It ends after the sender
+/// is closed and the last batch is flushed.
+///
+/// Reports are buffered until polled. Drop the stream if you are not
+/// interested in them.
+#[derive(Debug)]
+pub struct FlushReports {
+    rx: mpsc::UnboundedReceiver<FlushReport>,
+}
+
+impl Stream for FlushReports {
+    type Item = FlushReport;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+struct Queue {
+    events: VecDeque<(Instant, Event)>,
+    dropped_since_report: u64,
+    dropped_total: u64,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // wakes the flush task
+    not_empty: Notify,
+    // wakes senders waiting under `OverflowPolicy::Block`
+    not_full: Notify,
+    options: BatchOptions,
+}
+
+/// A non-blocking handle that buffers events and sends them to riemann in
+/// batches from a background task.
+///
+/// Events are flushed in a single message when `max_batch_size` events are
+/// queued or the oldest one has waited `max_delay_ms`. Dropping the sender,
+/// or calling `close`, flushes whatever is left in the queue.
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use futures::StreamExt;
+/// use rustmann::{BatchOptions, BatchSender, EventBuilder, RiemannClient, RiemannClientOptions};
+///
+/// # async fn run() {
+/// let client = Arc::new(RiemannClient::new(&RiemannClientOptions::default()));
+/// let (sender, mut reports) = BatchSender::new(client, &BatchOptions::default());
+///
+/// tokio::spawn(async move {
+///     while let Some(report) = reports.next().await {
+///         if let Err(e) = report.result() {
+///             eprintln!("failed to flush {} events: {}", report.events(), e);
+///         }
+///     }
+/// });
+///
+/// sender
+///     .send(EventBuilder::new().service("batched").build())
+///     .await
+///     .unwrap();
+/// sender.close().await;
+/// # }
+/// ```
+pub struct BatchSender {
+    shared: Arc<Shared>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl BatchSender {
+    /// Create a `BatchSender` on top of `client`, and the stream of its flush
+    /// results. The flush task is spawned on the current tokio runtime.
+    pub fn new(client: Arc<RiemannClient>, options: &BatchOptions) -> (BatchSender, FlushReports) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                dropped_since_report: 0,
+                dropped_total: 0,
+                closed: false,
+            }),
+            not_empty: Notify::new(),
+            not_full: Notify::new(),
+            options: options.clone(),
+        });
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(flush_loop(shared.clone(), client, tx));
+
+        (
+            BatchSender {
+                shared,
+                task: Some(task),
+            },
+            FlushReports { rx },
+        )
+    }
+
+    /// Enqueue an event without waiting. Under `OverflowPolicy::Block` a full
+    /// queue is reported as `BatchError::Full`.
+    pub fn try_send(&self, event: Event) -> Result<(), BatchError> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.closed {
+            return Err(BatchError::Closed(Box::new(event)));
+        }
+
+        if queue.events.len() >= self.shared.options.queue_capacity {
+            match self.shared.options.overflow_policy {
+                OverflowPolicy::Block => return Err(BatchError::Full(Box::new(event))),
+                OverflowPolicy::DropNewest => {
+                    queue.dropped_since_report += 1;
+                    queue.dropped_total += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::DropOldest => {
+                    queue.events.pop_front();
+                    queue.dropped_since_report += 1;
+                    queue.dropped_total += 1;
+                }
+            }
+        }
+
+        queue.events.push_back((Instant::now(), event));
+        // the flush task only needs waking for the first event, which arms
+        // the delay timer, and when a full batch is ready
+        let len = queue.events.len();
+        if len == 1 || len >= self.shared.options.max_batch_size {
+            self.shared.not_empty.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Enqueue an event. Only waits when the queue is full and the policy is
+    /// `OverflowPolicy::Block`.
+    pub async fn send(&self, mut event: Event) -> Result<(), BatchError> {
+        loop {
+            let notified = self.shared.not_full.notified();
+            futures::pin_mut!(notified);
+            // register before trying so a flush in between is not missed
+            notified.as_mut().enable();
+
+            match self.try_send(event) {
+                Err(BatchError::Full(e)) => {
+                    event = *e;
+                    notified.await;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// Enqueue several events, in order.
+    pub async fn send_events(&self, events: Vec<Event>) -> Result<(), BatchError> {
+        for event in events {
+            self.send(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Number of events currently waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.shared.queue.lock().unwrap().events.len()
+    }
+
+    /// Total number of events discarded by the overflow policy.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue.lock().unwrap().dropped_total
+    }
+
+    /// Stop accepting events and wait until the queue is flushed.
+    pub async fn close(mut self) {
+        self.shutdown();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+
+    fn shutdown(&self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.not_empty.notify_one();
+        self.shared.not_full.notify_waiters();
+    }
+}
+
+impl Drop for BatchSender {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+async fn flush_loop(
+    shared: Arc<Shared>,
+    client: Arc<RiemannClient>,
+    reports: mpsc::UnboundedSender<FlushReport>,
+) {
+    let max_batch_size = shared.options.max_batch_size;
+    let max_delay = Duration::from_millis(shared.options.max_delay_ms);
+
+    loop {
+        let (batch, dropped) = loop {
+            let deadline = {
+                let mut queue = shared.queue.lock().unwrap();
+                let deadline = queue.events.front().map(|(t, _)| *t + max_delay);
+                let due = deadline.map(|d| d <= Instant::now()).unwrap_or(false);
+
+                if queue.events.len() >= max_batch_size
+                    || (!queue.events.is_empty() && (due || queue.closed))
+                {
+                    let n = queue.events.len().min(max_batch_size);
+                    let batch: Vec<Event> = queue.events.drain(..n).map(|(_, e)| e).collect();
+                    let dropped = std::mem::take(&mut queue.dropped_since_report);
+                    break (batch, dropped);
+                } else if queue.closed {
+                    return;
+                }
+                deadline
+            };
+
+            match deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = shared.not_empty.notified() => {},
+                        _ = sleep_until(deadline) => {},
+                    }
+                }
+                None => shared.not_empty.notified().await,
+            }
+        };
+        shared.not_full.notify_waiters();
+
+        let events = batch.len();
+        let result = client.send_events(batch).await;
+        let _ = reports.send(FlushReport {
+            events,
+            dropped,
+            result,
+        });
+    }
+}
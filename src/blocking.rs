@@ -0,0 +1,132 @@
+//! A synchronous `RiemannClient`, for programs that don't run a tokio
+//! runtime of their own.
+
+use std::io;
+use std::sync::mpsc as std_mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+
+use crate::client::{RiemannClient as AsyncClient, ShutdownReport};
+use crate::error::RiemannClientError;
+use crate::options::RiemannClientOptions;
+use crate::protos::riemann::Event;
+
+type Reply<T> = std_mpsc::SyncSender<Result<T, RiemannClientError>>;
+
+enum Request {
+    SendEvents(Vec<Event>, Reply<()>),
+    SendQuery(String, Reply<Vec<Event>>),
+    Shutdown(Instant, Reply<ShutdownReport>),
+}
+
+/// A blocking riemann client.
+///
+/// The underlying async client runs on a single-threaded tokio runtime, in a
+/// thread owned by this client. Calls block the calling thread only, so they
+/// are fine outside of any runtime, from within an async context, or in a
+/// `Drop` impl while a runtime shuts down. Calls from several threads are
+/// served concurrently.
+///
+/// Dropping the client stops its thread.
+///
+/// ```no_run
+/// use rustmann::blocking::RiemannClient;
+/// use rustmann::{EventBuilder, RiemannClientOptions};
+///
+/// let client = RiemannClient::new(&RiemannClientOptions::default()).unwrap();
+/// client
+///     .send_events(vec![EventBuilder::new().service("batch_job").build()])
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct RiemannClient {
+    requests: Option<mpsc::UnboundedSender<Request>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RiemannClient {
+    /// Create a client from options, starting its thread.
+    pub fn new(options: &RiemannClientOptions) -> Result<Self, RiemannClientError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let options = options.clone();
+
+        let worker = thread::Builder::new()
+            .name("rustmann-blocking".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let client = std::sync::Arc::new(AsyncClient::new(&options));
+                    while let Some(request) = rx.recv().await {
+                        let client = client.clone();
+                        tokio::spawn(async move {
+                            match request {
+                                Request::SendEvents(events, reply) => {
+                                    let _ = reply.send(client.send_events(events).await);
+                                }
+                                Request::SendQuery(query, reply) => {
+                                    let _ = reply.send(client.send_query(query).await);
+                                }
+                                Request::Shutdown(deadline, reply) => {
+                                    let _ = reply.send(Ok(client.shutdown(deadline).await));
+                                }
+                            }
+                        });
+                    }
+                })
+            })?;
+
+        Ok(RiemannClient {
+            requests: Some(tx),
+            worker: Some(worker),
+        })
+    }
+
+    /// Send events to riemann, blocking until they are acknowledged.
+    pub fn send_events(&self, events: Vec<Event>) -> Result<(), RiemannClientError> {
+        self.call(|reply| Request::SendEvents(events, reply))
+    }
+
+    /// Query riemann, blocking until the matching events are returned.
+    pub fn send_query<S>(&self, query_string: S) -> Result<Vec<Event>, RiemannClientError>
+    where
+        S: AsRef<str>,
+    {
+        let query = query_string.as_ref().to_owned();
+        self.call(|reply| Request::SendQuery(query, reply))
+    }
+
+    /// Close the client, see the async `RiemannClient::shutdown`.
+    pub fn shutdown(&self, deadline: Instant) -> Result<ShutdownReport, RiemannClientError> {
+        self.call(|reply| Request::Shutdown(deadline, reply))
+    }
+
+    fn call<T, F>(&self, request: F) -> Result<T, RiemannClientError>
+    where
+        F: FnOnce(Reply<T>) -> Request,
+    {
+        let (reply, response) = std_mpsc::sync_channel(1);
+        self.requests
+            .as_ref()
+            .and_then(|requests| requests.send(request(reply)).ok())
+            .ok_or_else(worker_gone)?;
+        response.recv().map_err(|_| worker_gone())?
+    }
+}
+
+impl Drop for RiemannClient {
+    fn drop(&mut self) {
+        // closing the channel ends the worker loop
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_gone() -> RiemannClientError {
+    RiemannClientError::from(io::Error::other("riemann client thread has stopped"))
+}
@@ -1,93 +1,564 @@
-use std::ops::DerefMut;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 
 use futures::lock::Mutex;
+use getset::Getters;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::timeout_at;
 
-use crate::error::RiemannClientError;
-use crate::options::RiemannClientOptions;
-use crate::protos::riemann::{Event, Query};
-use crate::state::{ClientState, Inner};
+use crate::error::{Operation, RiemannClientError};
+use crate::options::{Endpoint, RiemannClientOptions};
+use crate::pool::Pool;
+use crate::protos::riemann::{Event, Msg, Query};
+use crate::retry::{backoff, RetryPolicy};
+use crate::spool::Spool;
+#[cfg(feature = "websocket")]
+use crate::subscribe::Subscription;
+#[cfg(feature = "tls")]
+use crate::tls::{watch_pem_files, TlsSource};
+use crate::transport::Transport;
+use crate::validate::Validation;
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::ClientConfig;
 
 pub struct RiemannClient {
-    inner: Mutex<Inner>,
+    pool: Arc<Pool>,
     options: RiemannClientOptions,
+    active_endpoint: Arc<AtomicUsize>,
+    retry_policy: RetryPolicy,
+    retries: AtomicU64,
+    spool: Option<Arc<SpoolReplay>>,
+    in_flight: InFlight,
+    closed: AtomicBool,
+    // warm up and TLS file watching
+    tasks: StdMutex<Vec<JoinHandle<()>>>,
+}
+
+/// What was left undelivered by `RiemannClient::shutdown`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct ShutdownReport {
+    /// Calls still in progress at the deadline
+    abandoned_calls: usize,
+    /// Events sent by the abandoned calls, which may or may not have reached
+    /// riemann
+    abandoned_events: usize,
+    /// Batches left in the spool, for the next client using it
+    spooled: u64,
+}
+
+impl ShutdownReport {
+    /// Whether everything was delivered.
+    pub fn is_complete(&self) -> bool {
+        self.abandoned_calls == 0 && self.spooled == 0
+    }
+}
+
+/// Calls in progress
+#[derive(Default)]
+struct InFlight {
+    calls: AtomicUsize,
+    events: AtomicUsize,
+    idle: Notify,
+}
+
+/// Counts a call as in progress until dropped.
+struct CallGuard<'a> {
+    in_flight: &'a InFlight,
+    events: usize,
+}
+
+impl<'a> Drop for CallGuard<'a> {
+    fn drop(&mut self) {
+        self.in_flight
+            .events
+            .fetch_sub(self.events, Ordering::SeqCst);
+        if self.in_flight.calls.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
 }
 
 impl RiemannClient {
     /// Create `RiemannClient` from options.
+    ///
+    /// With `pool_warm_up` set and a tokio runtime available, all pooled
+    /// connections are opened in background right away. Likewise, with
+    /// `tls_reload_interval_ms` set, the TLS files are watched in background.
     pub fn new(options: &RiemannClientOptions) -> Self {
+        let options = options.for_client();
+        let active_endpoint = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(Pool::new(&options, active_endpoint.clone()));
+        let mut tasks = Vec::new();
+
+        if *options.pool_warm_up() {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let pool = pool.clone();
+                tasks.push(runtime.spawn(async move {
+                    let _ = pool.warm_up().await;
+                }));
+            }
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            let interval = *options.tls_reload_interval_ms();
+            if let (Some(source), true) = (options.tls_source(), interval > 0) {
+                if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                    tasks.push(runtime.spawn(watch_pem_files(
+                        Arc::downgrade(source),
+                        Duration::from_millis(interval),
+                    )));
+                }
+            }
+        }
+
         RiemannClient {
-            inner: Mutex::new(Inner {
-                state: ClientState::Disconnected,
-                options: options.clone(),
-            }),
-            options: options.clone(),
+            pool,
+            options,
+            active_endpoint,
+            retry_policy: RetryPolicy::none(),
+            retries: AtomicU64::new(0),
+            spool: None,
+            in_flight: InFlight::default(),
+            closed: AtomicBool::new(false),
+            tasks: StdMutex::new(tasks),
         }
     }
 
-    /// Send events to riemann via this client.
-    pub async fn send_events(&self, events: Vec<Event>) -> Result<(), RiemannClientError> {
-        let timeout = *self.options.socket_timeout_ms();
+    /// Retry failed calls according to `policy`. By default nothing is
+    /// retried.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
 
-        let conn = {
-            let mut inner = self.inner.lock().await;
-            let i = inner.deref_mut();
-            i.await?
-        };
+    /// Open every pooled connection that is not open yet.
+    pub async fn connect(&self) -> Result<(), RiemannClientError> {
+        self.pool.warm_up().await
+    }
+
+    /// Keep batches that cannot be delivered in `spool`, and deliver them
+    /// first, in order, once riemann is reachable again.
+    ///
+    /// With a spool, `send_events` returns `Ok` for a batch that failed to
+    /// send but was spooled. Batches rejected by the riemann server, or that
+    /// can never be sent, are never spooled. Spooled batches are replayed
+    /// before the next `send_events` call, explicitly with `flush_spool`,
+    /// and, with a tokio runtime available, in background: each time a
+    /// connection is made, and with backoff while riemann is unreachable.
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        let pending = !spool.is_empty();
+        let replay = Arc::new(SpoolReplay {
+            spool: Arc::new(StdMutex::new(spool)),
+            pool: self.pool.clone(),
+            options: self.options.clone(),
+            lock: Mutex::new(()),
+            spooled: Notify::new(),
+        });
+        if pending {
+            replay.spooled.notify_one();
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let task = runtime.spawn(replay_in_background(replay.clone()));
+            self.tasks.get_mut().unwrap().push(task);
+        }
+        self.spool = Some(replay);
+        self
+    }
+
+    /// Number of batches waiting in the spool, if there is one.
+    pub fn spooled(&self) -> Option<u64> {
+        self.spool
+            .as_ref()
+            .map(|s| s.spool.lock().unwrap().pending())
+    }
+
+    /// Total number of retries this client has made.
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
 
-        match conn.send_events(events, timeout).await {
-            Ok(msg) => {
-                if msg.ok.unwrap_or(false) {
-                    Ok(())
-                } else {
-                    Err(RiemannClientError::RiemannError(
-                        msg.error.unwrap_or_else(|| "".to_owned()),
-                    ))
+    /// The endpoint this client is connected to, or will try first on its
+    /// next connection attempt. With a pool of connections, this is the
+    /// endpoint of the most recent connection.
+    pub fn active_endpoint(&self) -> &Endpoint {
+        &self.options.endpoints()[self.active_endpoint.load(Ordering::Relaxed)]
+    }
+
+    /// Load the TLS certificate and key files again, for instance after they
+    /// were rotated. New connections use the new files, and each existing
+    /// connection is replaced on its next use, while calls already running
+    /// on it finish. The current files stay in use if the new ones cannot be
+    /// loaded.
+    #[cfg(feature = "tls")]
+    pub fn reload_tls(&self) -> Result<(), RiemannClientError> {
+        self.tls_source()?.reload()
+    }
+
+    /// Replace the TLS config, like `reload_tls` does with the config loaded
+    /// from files.
+    #[cfg(feature = "tls")]
+    pub fn set_tls_config(&self, tls_config: Arc<ClientConfig>) -> Result<(), RiemannClientError> {
+        self.tls_source()?.replace(tls_config);
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_source(&self) -> Result<&Arc<TlsSource>, RiemannClientError> {
+        self.options
+            .tls_source()
+            .as_ref()
+            .ok_or_else(|| RiemannClientError::InvalidOptions("TLS is not enabled".to_owned()))
+    }
+
+    /// Send events to riemann via this client, merged with the
+    /// `event_defaults` and validated according to the `validation` of the
+    /// options.
+    pub async fn send_events(&self, mut events: Vec<Event>) -> Result<(), RiemannClientError> {
+        let _call = self.begin_call(events.len())?;
+        if let Some(defaults) = self.options.event_defaults() {
+            for event in events.iter_mut() {
+                event.merge_defaults(defaults);
+            }
+        }
+        match self.options.validation() {
+            Validation::Off => {}
+            Validation::Reject => {
+                for (index, event) in events.iter().enumerate() {
+                    event
+                        .validate()
+                        .map_err(|error| RiemannClientError::Validation { index, error })?;
                 }
             }
-            Err(e) => {
-                let mut inner = self.inner.lock().await;
-                let i = inner.deref_mut();
-                i.state = ClientState::Disconnected;
+            Validation::Sanitize => events.iter_mut().for_each(Event::sanitize),
+        }
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => return self.send_events_with_retry(events).await,
+        };
 
-                Err(RiemannClientError::from(e))
-            }
+        // keep the original order: nothing new goes out before the spool is
+        // drained
+        match spool.replay().await {
+            Err(e) if e.is_spoolable() => return spool.append(events, e).await,
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+        match self.send_events_with_retry(events.clone()).await {
+            Err(e) if e.is_spoolable() => spool.append(events, e).await,
+            r => r,
         }
     }
 
+    /// Deliver the spooled batches, oldest first, stopping at the first
+    /// failure.
+    pub async fn flush_spool(&self) -> Result<(), RiemannClientError> {
+        match self.spool {
+            Some(ref spool) => spool.replay().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_events_with_retry(
+        &self,
+        mut events: Vec<Event>,
+    ) -> Result<(), RiemannClientError> {
+        let max_attempts = self.retry_policy.max_attempts();
+        self.with_retry(|attempt| {
+            // only keep a copy around while another attempt may follow
+            let events = if attempt < max_attempts {
+                events.clone()
+            } else {
+                std::mem::take(&mut events)
+            };
+            self.try_send_events(events)
+        })
+        .await
+    }
+
+    async fn try_send_events(&self, events: Vec<Event>) -> Result<(), RiemannClientError> {
+        send_batch(&self.pool, &self.options, events).await
+    }
+
     /// Query riemann server by riemann query syntax via this client.
     pub async fn send_query<S>(&self, query_string: S) -> Result<Vec<Event>, RiemannClientError>
     where
         S: AsRef<str>,
     {
+        let _call = self.begin_call(0)?;
+        let query = Query {
+            string: Some(query_string.as_ref().to_owned()),
+        };
+        self.with_retry(|_| self.try_send_query(query.clone()))
+            .await
+    }
+
+    async fn try_send_query(&self, query: Query) -> Result<Vec<Event>, RiemannClientError> {
         let timeout = *self.options.socket_timeout_ms();
+        let slot = self.pool.checkout();
+        let conn = slot.connection().await?;
 
-        let conn = {
-            let mut inner = self.inner.lock().await;
-            let i = inner.deref_mut();
-            i.await?
+        match conn.query(query, timeout).await {
+            Ok(msg) => ok_or_server_error(msg, &conn, Operation::Query).map(|msg| msg.events),
+            Err(e) => {
+                if breaks_connection(&e) {
+                    slot.reset(&conn).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Subscribe to the events matching `query_string`, as riemann indexes
+    /// them, via riemann's websocket server on `ws_port` of the active
+    /// endpoint.
+    ///
+    /// Must be called within a tokio runtime.
+    #[cfg(feature = "websocket")]
+    pub fn subscribe<S>(&self, query_string: S) -> Subscription
+    where
+        S: AsRef<str>,
+    {
+        Subscription::new(&self.options, self.active_endpoint(), query_string.as_ref())
+    }
+
+    /// Close the client, waiting for calls in progress to finish as long as
+    /// needed. See `shutdown`.
+    pub async fn close(&self) -> ShutdownReport {
+        self.shutdown_until(None).await
+    }
+
+    /// Close the client: new calls fail with `RiemannClientError::Closed`,
+    /// calls in progress get until `deadline` to finish, and so does a last
+    /// attempt to deliver the spool. Then connections are closed cleanly and
+    /// background tasks stopped.
+    ///
+    /// Calls still running at the deadline are abandoned, their connection
+    /// closes once they are done.
+    pub async fn shutdown(&self, deadline: Instant) -> ShutdownReport {
+        self.shutdown_until(Some(deadline.into())).await
+    }
+
+    async fn shutdown_until(&self, deadline: Option<tokio::time::Instant>) -> ShutdownReport {
+        self.closed.store(true, Ordering::SeqCst);
+
+        let finish = async {
+            loop {
+                let idle = self.in_flight.idle.notified();
+                tokio::pin!(idle);
+                idle.as_mut().enable();
+                if self.in_flight.calls.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                idle.await;
+            }
+            let _ = self.flush_spool().await;
         };
+        match deadline {
+            Some(deadline) => {
+                let _ = timeout_at(deadline, finish).await;
+            }
+            None => finish.await,
+        }
 
-        let query = Query {
-            string: Some(query_string.as_ref().to_owned()),
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            task.abort();
+            let _ = task.await;
+        }
+        let socket_timeout = Duration::from_millis(*self.options.socket_timeout_ms());
+        self.pool.close(socket_timeout).await;
+
+        ShutdownReport {
+            abandoned_calls: self.in_flight.calls.load(Ordering::SeqCst),
+            abandoned_events: self.in_flight.events.load(Ordering::SeqCst),
+            spooled: self.spooled().unwrap_or(0),
+        }
+    }
+
+    /// Count a call as in progress, unless the client is closed.
+    fn begin_call(&self, events: usize) -> Result<CallGuard<'_>, RiemannClientError> {
+        self.in_flight.calls.fetch_add(1, Ordering::SeqCst);
+        self.in_flight.events.fetch_add(events, Ordering::SeqCst);
+        let call = CallGuard {
+            in_flight: &self.in_flight,
+            events,
         };
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(RiemannClientError::Closed);
+        }
+        Ok(call)
+    }
 
-        match conn.query(query, timeout).await {
-            Ok(msg) => {
-                if msg.ok.unwrap_or(false) {
-                    Ok(msg.events)
-                } else {
-                    Err(RiemannClientError::RiemannError(
-                        msg.error.unwrap_or_else(|| "".to_owned()),
-                    ))
+    /// Run `attempt_fn`, numbered from 1, until it succeeds or the retry
+    /// policy gives up.
+    async fn with_retry<T, F, Fut>(&self, mut attempt_fn: F) -> Result<T, RiemannClientError>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, RiemannClientError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match attempt_fn(attempt).await {
+                Err(e) if self.retry_policy.should_retry(&e, attempt) => {
+                    tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    attempt += 1;
                 }
+                r => return r,
             }
-            Err(e) => {
-                let mut inner = self.inner.lock().await;
-                let i = inner.deref_mut();
-                i.state = ClientState::Disconnected;
+        }
+    }
+}
 
-                Err(RiemannClientError::from(e))
+impl Drop for RiemannClient {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+/// Run blocking spool IO off the async executor.
+async fn spool_op<T, F>(spool: &Arc<StdMutex<Spool>>, f: F) -> Result<T, RiemannClientError>
+where
+    F: FnOnce(&mut Spool) -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let spool = spool.clone();
+    tokio::task::spawn_blocking(move || f(&mut spool.lock().unwrap()))
+        .await
+        .map_err(io::Error::other)?
+        .map_err(RiemannClientError::from)
+}
+
+/// Send `events` over a pooled connection, dropping the connection when it
+/// broke.
+async fn send_batch(
+    pool: &Pool,
+    options: &RiemannClientOptions,
+    events: Vec<Event>,
+) -> Result<(), RiemannClientError> {
+    let timeout = *options.socket_timeout_ms();
+    let slot = pool.checkout();
+    let conn = slot.connection().await?;
+
+    match conn.send_events(events, timeout).await {
+        Ok(msg) => ok_or_server_error(msg, &conn, Operation::SendEvents).map(|_| ()),
+        Err(e) => {
+            if breaks_connection(&e) {
+                slot.reset(&conn).await;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// A spool and what it takes to replay it, shared with the background
+/// replay task
+struct SpoolReplay {
+    spool: Arc<StdMutex<Spool>>,
+    pool: Arc<Pool>,
+    options: RiemannClientOptions,
+    // only one caller replays the spool at a time
+    lock: Mutex<()>,
+    /// notified when a batch is spooled
+    spooled: Notify,
+}
+
+impl SpoolReplay {
+    /// Deliver the spooled batches, oldest first, stopping at the first
+    /// failure.
+    async fn replay(&self) -> Result<(), RiemannClientError> {
+        if self.spool.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.lock.lock().await;
+        while let Some(msg) = spool_op(&self.spool, |s| s.peek()).await? {
+            match send_batch(&self.pool, &self.options, msg.events).await {
+                // a batch the server rejects would block the spool forever,
+                // so it is discarded like a delivered one
+                Err(e) if !e.is_request_error() => return Err(e),
+                _ => spool_op(&self.spool, |s| s.commit()).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Spool a batch that could not be sent. The send `error` is returned
+    /// when the spool cannot take it.
+    async fn append(
+        &self,
+        events: Vec<Event>,
+        error: RiemannClientError,
+    ) -> Result<(), RiemannClientError> {
+        let msg = Msg {
+            events,
+            ..Default::default()
+        };
+        match spool_op(&self.spool, move |s| s.append(&msg)).await {
+            Ok(true) => {
+                self.spooled.notify_one();
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+/// Replay the spool each time a connection is made, and while it is not
+/// empty, with backoff until riemann is reachable, so that a client that
+/// stops sending still delivers its spool.
+async fn replay_in_background(replay: Arc<SpoolReplay>) {
+    let mut failures = 0;
+    loop {
+        if failures == 0 {
+            tokio::select! {
+                _ = replay.pool.connected().notified() => {}
+                _ = replay.spooled.notified() => {}
             }
+        } else {
+            tokio::time::sleep(backoff(
+                *replay.options.reconnect_backoff_ms(),
+                *replay.options.reconnect_backoff_max_ms(),
+                failures,
+            ))
+            .await;
         }
+
+        failures = match replay.replay().await {
+            Err(e) if e.is_spoolable() => failures.saturating_add(1),
+            // delivered, or stuck until the options change
+            _ => 0,
+        };
+    }
+}
+
+/// Whether a call that failed with `error` leaves its connection unusable. A
+/// timed out call does not: its response is discarded when it arrives, and
+/// the other calls pipelined on the connection carry on.
+fn breaks_connection(error: &RiemannClientError) -> bool {
+    !error.is_request_error() && !matches!(error, RiemannClientError::ResponseTimeout { .. })
+}
+
+fn ok_or_server_error(
+    msg: Msg,
+    conn: &Transport,
+    operation: Operation,
+) -> Result<Msg, RiemannClientError> {
+    if msg.ok.unwrap_or(false) {
+        Ok(msg)
+    } else {
+        Err(RiemannClientError::Server {
+            endpoint: conn.endpoint().to_owned(),
+            operation,
+            message: msg.error.unwrap_or_default(),
+        })
     }
 }
@@ -1,11 +1,15 @@
 use prost::bytes::{Buf, BufMut, BytesMut};
+use prost::encoding::{encoded_len_varint, key_len};
 use prost::Message;
 use tokio_util::codec::{Decoder, Encoder};
 
 use std::io;
-use std::usize;
 
-use crate::protos::riemann::Msg;
+use crate::error::RiemannClientError;
+use crate::protos::riemann::{Event, Msg};
+
+/// Field number of `events` in `Msg`
+const MSG_EVENTS_TAG: u32 = 6;
 
 #[derive(Debug, Default)]
 pub struct MsgCodec {
@@ -47,9 +51,45 @@ impl Decoder for MsgCodec {
     }
 }
 
-pub(crate) fn encode_for_udp(msg: &Msg) -> Result<BytesMut, io::Error> {
-    let mut buf = BytesMut::new();
+/// Encode `events` into as few messages as possible, each at most
+/// `max_size` bytes, keeping their order.
+pub(crate) fn encode_for_udp(
+    events: Vec<Event>,
+    max_size: usize,
+) -> Result<Vec<BytesMut>, RiemannClientError> {
+    let mut batches: Vec<Msg> = Vec::new();
+    let mut batch_size = 0;
+
+    for event in events {
+        // an event is a length delimited field of the message
+        let len = event.encoded_len();
+        let size = key_len(MSG_EVENTS_TAG) + encoded_len_varint(len as u64) + len;
+        if size > max_size {
+            return Err(RiemannClientError::DatagramTooLarge {
+                size,
+                max: max_size,
+            });
+        }
+
+        match batches.last_mut() {
+            Some(msg) if batch_size + size <= max_size => msg.events.push(event),
+            _ => {
+                batches.push(Msg {
+                    events: vec![event],
+                    ..Default::default()
+                });
+                batch_size = 0;
+            }
+        }
+        batch_size += size;
+    }
 
-    msg.encode(&mut buf).map_err(io::Error::from)?;
-    Ok(buf)
+    batches
+        .iter()
+        .map(|msg| {
+            let mut buf = BytesMut::with_capacity(msg.encoded_len());
+            msg.encode(&mut buf).map_err(io::Error::from)?;
+            Ok(buf)
+        })
+        .collect()
 }
@@ -0,0 +1,236 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::RiemannClientError;
+use crate::options::{Protocol, RiemannClientOptions, RiemannClientOptionsBuilder};
+
+/// Connection settings as read from a config file section or from
+/// environment variables. Every setting is optional.
+///
+/// With the `serde` feature, it deserializes from a config section such as:
+///
+/// ```toml
+/// [riemann]
+/// host = "riemann.example.com"
+/// protocol = "tls"
+/// tls_ca = "/etc/riemann/ca.pem"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RiemannClientConfig {
+    /// Connection URL, see `RiemannClientOptionsBuilder::from_url`. The
+    /// other settings of the same source override it.
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub protocol: Option<Protocol>,
+    /// Socket path for `Protocol::Unix`. Implies it when no protocol is set.
+    pub unix_socket: Option<PathBuf>,
+    pub connect_timeout_ms: Option<u64>,
+    pub socket_timeout_ms: Option<u64>,
+    pub pool_size: Option<usize>,
+    pub max_in_flight: Option<usize>,
+    pub tls_ca: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_server_name: Option<String>,
+}
+
+impl RiemannClientConfig {
+    /// Read the settings from environment variables named after them with
+    /// `prefix`, for instance `RIEMANN_HOST`, `RIEMANN_PORT`,
+    /// `RIEMANN_PROTOCOL` and `RIEMANN_TLS_CA` for the prefix `RIEMANN`.
+    /// Empty variables are ignored.
+    pub fn from_env(prefix: &str) -> Result<RiemannClientConfig, RiemannClientError> {
+        Self::from_vars(prefix, |name| env::var_os(name))
+    }
+
+    fn from_vars<F>(prefix: &str, lookup: F) -> Result<RiemannClientConfig, RiemannClientError>
+    where
+        F: Fn(&str) -> Option<std::ffi::OsString>,
+    {
+        let var = |suffix: &str| -> Result<Option<(String, String)>, RiemannClientError> {
+            let name = format!("{}_{}", prefix, suffix);
+            match lookup(&name) {
+                Some(value) if value.is_empty() => Ok(None),
+                Some(value) => match value.into_string() {
+                    Ok(value) => Ok(Some((name, value))),
+                    Err(_) => Err(invalid_var(&name, "not valid unicode")),
+                },
+                None => Ok(None),
+            }
+        };
+        let string = |suffix: &str| var(suffix).map(|v| v.map(|(_, value)| value));
+        let path = |suffix: &str| var(suffix).map(|v| v.map(|(_, value)| PathBuf::from(value)));
+
+        Ok(RiemannClientConfig {
+            url: string("URL")?,
+            host: string("HOST")?,
+            port: parse_var(var("PORT")?)?,
+            protocol: parse_var(var("PROTOCOL")?)?,
+            unix_socket: path("UNIX_SOCKET")?,
+            connect_timeout_ms: parse_var(var("CONNECT_TIMEOUT_MS")?)?,
+            socket_timeout_ms: parse_var(var("SOCKET_TIMEOUT_MS")?)?,
+            pool_size: parse_var(var("POOL_SIZE")?)?,
+            max_in_flight: parse_var(var("MAX_IN_FLIGHT")?)?,
+            tls_ca: path("TLS_CA")?,
+            tls_cert: path("TLS_CERT")?,
+            tls_key: path("TLS_KEY")?,
+            tls_server_name: string("TLS_SERVER_NAME")?,
+        })
+    }
+
+    /// Combine with `other`, whose settings win.
+    pub fn merge(self, other: RiemannClientConfig) -> RiemannClientConfig {
+        RiemannClientConfig {
+            url: other.url.or(self.url),
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+            protocol: other.protocol.or(self.protocol),
+            unix_socket: other.unix_socket.or(self.unix_socket),
+            connect_timeout_ms: other.connect_timeout_ms.or(self.connect_timeout_ms),
+            socket_timeout_ms: other.socket_timeout_ms.or(self.socket_timeout_ms),
+            pool_size: other.pool_size.or(self.pool_size),
+            max_in_flight: other.max_in_flight.or(self.max_in_flight),
+            tls_ca: other.tls_ca.or(self.tls_ca),
+            tls_cert: other.tls_cert.or(self.tls_cert),
+            tls_key: other.tls_key.or(self.tls_key),
+            tls_server_name: other.tls_server_name.or(self.tls_server_name),
+        }
+    }
+}
+
+fn parse_var<T: FromStr>(var: Option<(String, String)>) -> Result<Option<T>, RiemannClientError> {
+    match var {
+        Some((name, value)) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid_var(&name, &format!("invalid value {}", value))),
+        None => Ok(None),
+    }
+}
+
+fn invalid_var(name: &str, reason: &str) -> RiemannClientError {
+    RiemannClientError::InvalidOptions(format!("{}: {}", name, reason))
+}
+
+impl RiemannClientOptionsBuilder {
+    /// Apply the settings of `config` on top of the current ones: first its
+    /// `url`, then its other settings.
+    pub fn with_config(
+        self,
+        config: &RiemannClientConfig,
+    ) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+        let mut builder = match config.url {
+            Some(ref url) => self.with_url(url)?,
+            None => self,
+        };
+
+        match (config.protocol, &config.unix_socket) {
+            (Some(protocol), path) => builder = builder.set_protocol(protocol, path.clone())?,
+            (None, Some(path)) => {
+                builder = builder.set_protocol(Protocol::Unix, Some(path.clone()))?
+            }
+            (None, None) => {}
+        }
+
+        if let Some(ref host) = config.host {
+            builder = builder.host(host.clone());
+        }
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(ms) = config.connect_timeout_ms {
+            builder = builder.connect_timeout_ms(ms);
+        }
+        if let Some(ms) = config.socket_timeout_ms {
+            builder = builder.socket_timeout_ms(ms);
+        }
+        if let Some(size) = config.pool_size {
+            builder = builder.pool_size(size);
+        }
+        if let Some(n) = config.max_in_flight {
+            builder = builder.max_in_flight(n);
+        }
+
+        with_tls_config(builder, config)
+    }
+
+    /// Apply the settings from environment variables, see
+    /// `RiemannClientConfig::from_env`, on top of the current ones.
+    pub fn with_env(self, prefix: &str) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+        self.with_config(&RiemannClientConfig::from_env(prefix)?)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn with_tls_config(
+    mut builder: RiemannClientOptionsBuilder,
+    config: &RiemannClientConfig,
+) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+    if let Some(ref path) = config.tls_ca {
+        builder = builder.tls_ca_file(path.clone());
+    }
+    if let Some(ref path) = config.tls_cert {
+        builder = builder.tls_cert_file(path.clone());
+    }
+    if let Some(ref path) = config.tls_key {
+        builder = builder.tls_key_file(path.clone());
+    }
+    if let Some(ref name) = config.tls_server_name {
+        builder = builder.tls_server_name(name.clone());
+    }
+    Ok(builder)
+}
+
+#[cfg(not(feature = "tls"))]
+fn with_tls_config(
+    builder: RiemannClientOptionsBuilder,
+    config: &RiemannClientConfig,
+) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+    if config.tls_ca.is_some()
+        || config.tls_cert.is_some()
+        || config.tls_key.is_some()
+        || config.tls_server_name.is_some()
+    {
+        return Err(RiemannClientError::InvalidOptions(
+            "TLS settings require the tls feature".to_owned(),
+        ));
+    }
+    Ok(builder)
+}
+
+impl RiemannClientOptions {
+    /// Load options from an optional config file section, overridden by the
+    /// environment variables with `env_prefix`, see
+    /// `RiemannClientConfig::from_env`. Settings found in neither keep their
+    /// default.
+    ///
+    /// Each source is applied in full, its url and then its other settings,
+    /// so an environment url overrides the host and port of the file.
+    ///
+    /// ```no_run
+    /// use rustmann::RiemannClientOptions;
+    ///
+    /// let options = RiemannClientOptions::load(None, "RIEMANN").unwrap();
+    /// ```
+    pub fn load(
+        config: Option<&RiemannClientConfig>,
+        env_prefix: &str,
+    ) -> Result<RiemannClientOptions, RiemannClientError> {
+        let env = RiemannClientConfig::from_env(env_prefix)?;
+        let mut builder = RiemannClientOptionsBuilder::default();
+        if let Some(config) = config {
+            builder = builder.with_config(config)?;
+        }
+        builder.with_config(&env)?.try_build()
+    }
+}
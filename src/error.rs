@@ -1,12 +1,255 @@
+use std::fmt;
 use std::io;
+use std::time::Duration;
 
+use getset::Getters;
 use thiserror::Error;
 
+use crate::protos::riemann::Event;
+
 /// The error type
 #[derive(Error, Debug)]
 pub enum RiemannClientError {
     #[error("IO error: {0}")]
     IoError(#[from] io::Error),
-    #[error("Riemann error: {0}")]
-    RiemannError(String),
+    #[error("Failed to connect to {endpoint}: {source}")]
+    Connect {
+        endpoint: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Timed out connecting to {endpoint} after {timeout:?}")]
+    ConnectTimeout { endpoint: String, timeout: Duration },
+    #[error("No response from {endpoint} to {operation} within {timeout:?}")]
+    ResponseTimeout {
+        endpoint: String,
+        operation: Operation,
+        timeout: Duration,
+    },
+    #[error("Connection to {endpoint} closed during {operation}")]
+    ConnectionClosed {
+        endpoint: String,
+        operation: Operation,
+    },
+    #[error("{operation} is not supported over {transport}")]
+    Unsupported {
+        operation: Operation,
+        transport: &'static str,
+    },
+    #[error("Invalid response from {endpoint}: {reason}")]
+    Protocol { endpoint: String, reason: String },
+    #[error("TLS error with {endpoint}: {source}")]
+    Tls {
+        endpoint: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Riemann server {endpoint} failed {operation}: {message}")]
+    Server {
+        endpoint: String,
+        operation: Operation,
+        message: String,
+    },
+    #[error("Circuit breaker open, next connection attempt in {0:?}")]
+    CircuitOpen(Duration),
+    #[error("Invalid event: {0}")]
+    InvalidEvent(String),
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+    #[error("Event of {size} bytes does not fit in a {max} byte UDP datagram")]
+    DatagramTooLarge { size: usize, max: usize },
+    #[error("Client is closed")]
+    Closed,
+    #[error("Event {index} of the batch is invalid: {error}")]
+    Validation {
+        index: usize,
+        #[source]
+        error: ValidationError,
+    },
+}
+
+/// The call a `RiemannClientError` happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    SendEvents,
+    Query,
+    Subscribe,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::SendEvents => f.write_str("send_events"),
+            Operation::Query => f.write_str("query"),
+            Operation::Subscribe => f.write_str("subscribe"),
+        }
+    }
+}
+
+impl RiemannClientError {
+    /// Whether the same call may succeed if tried again, typically over a
+    /// new connection: connection failures, timeouts and closed
+    /// connections. An open circuit breaker is not retryable, its point is
+    /// to fail fast until the cooldown is over. Neither is `IoError`, which
+    /// covers local failures such as the spool or a UDP socket bind.
+    ///
+    /// A call that timed out waiting for its response may have been
+    /// processed by riemann, so trying it again can send events twice.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            RiemannClientError::Connect { .. }
+                | RiemannClientError::ConnectTimeout { .. }
+                | RiemannClientError::ResponseTimeout { .. }
+                | RiemannClientError::ConnectionClosed { .. }
+        )
+    }
+
+    /// A TLS failure with `endpoint`. A handshake cut off by the peer is a
+    /// connection failure, not a certificate or protocol one.
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(endpoint: String, source: io::Error) -> RiemannClientError {
+        match source.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe => RiemannClientError::Connect { endpoint, source },
+            _ => RiemannClientError::Tls { endpoint, source },
+        }
+    }
+
+    /// Whether the request itself is at fault, so sending it again can never
+    /// succeed.
+    pub(crate) fn is_request_error(&self) -> bool {
+        matches!(
+            self,
+            RiemannClientError::Server { .. }
+                | RiemannClientError::InvalidEvent(_)
+                | RiemannClientError::DatagramTooLarge { .. }
+                | RiemannClientError::Unsupported { .. }
+                | RiemannClientError::Validation { .. }
+        )
+    }
+
+    /// Whether a batch that failed with this error may be delivered later,
+    /// so it is worth spooling. Invalid options and a closed client fail
+    /// every attempt, like a faulty request.
+    pub(crate) fn is_spoolable(&self) -> bool {
+        !self.is_request_error()
+            && !matches!(
+                self,
+                RiemannClientError::InvalidOptions(_) | RiemannClientError::Closed
+            )
+    }
+}
+
+/// The error type for enqueuing events to a `BatchSender`. The rejected event
+/// is handed back to the caller.
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("Batch queue is full")]
+    Full(Box<Event>),
+    #[error("Batch sender is closed")]
+    Closed(Box<Event>),
+}
+
+impl BatchError {
+    /// Take back the event that was not enqueued.
+    pub fn into_event(self) -> Event {
+        match self {
+            BatchError::Full(e) | BatchError::Closed(e) => *e,
+        }
+    }
+}
+
+/// The error type of `ReplicatedClient::send_events`, returned when fewer
+/// replicas acknowledged than the `ReplicationPolicy` requires.
+#[derive(Error, Debug, Getters)]
+#[error(
+    "{acknowledged} replica(s) acknowledged, {required} required: {}",
+    display_failures(failures)
+)]
+#[get = "pub"]
+pub struct ReplicationError {
+    /// Every replica that failed, including those the policy could tolerate
+    failures: Vec<ReplicaFailure>,
+    acknowledged: usize,
+    required: usize,
+}
+
+impl ReplicationError {
+    pub(crate) fn new(
+        failures: Vec<ReplicaFailure>,
+        acknowledged: usize,
+        required: usize,
+    ) -> ReplicationError {
+        ReplicationError {
+            failures,
+            acknowledged,
+            required,
+        }
+    }
+}
+
+/// A failed send to one replica
+#[derive(Error, Debug, Getters)]
+#[error("{replica}: {error}")]
+#[get = "pub"]
+pub struct ReplicaFailure {
+    replica: String,
+    #[source]
+    error: RiemannClientError,
+}
+
+impl ReplicaFailure {
+    pub(crate) fn new(replica: String, error: RiemannClientError) -> ReplicaFailure {
+        ReplicaFailure { replica, error }
+    }
+}
+
+fn display_failures(failures: &[ReplicaFailure]) -> String {
+    failures
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The error type of event validation, listing every invalid field
+#[derive(Error, Debug, Clone, PartialEq, Eq, Getters)]
+#[error("{}", display_fields(fields))]
+#[get = "pub"]
+pub struct ValidationError {
+    fields: Vec<InvalidField>,
+}
+
+impl ValidationError {
+    pub(crate) fn new(fields: Vec<InvalidField>) -> ValidationError {
+        ValidationError { fields }
+    }
+}
+
+/// An invalid field of an event, like `ttl` or `attributes[2].key`
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[get = "pub"]
+pub struct InvalidField {
+    field: String,
+    reason: String,
+}
+
+impl InvalidField {
+    pub(crate) fn new<S: Into<String>>(field: S, reason: &str) -> InvalidField {
+        InvalidField {
+            field: field.into(),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+fn display_fields(fields: &[InvalidField]) -> String {
+    fields
+        .iter()
+        .map(|f| format!("{} {}", f.field, f.reason))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
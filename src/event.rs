@@ -1,9 +1,144 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use crate::protos::riemann::{Attribute, Event};
 
+/// The metric of an event, in one of the three representations riemann
+/// supports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// `metric_sint64`
+    Int(i64),
+    /// `metric_d`
+    Double(f64),
+    /// `metric_f`
+    Float(f32),
+}
+
+impl Metric {
+    /// The metric as a double, if it is exactly representable as one. Only
+    /// integers beyond 2^53 in magnitude may not be.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Metric::Int(i) => {
+                let d = i as f64;
+                // i64::MAX rounds up to 2^63, which is out of i64 range
+                if d < 9_223_372_036_854_775_808.0 && d as i64 == i {
+                    Some(d)
+                } else {
+                    None
+                }
+            }
+            Metric::Double(d) => Some(d),
+            Metric::Float(f) => Some(f64::from(f)),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::Int(i) => i.fmt(f),
+            Metric::Double(d) => d.fmt(f),
+            Metric::Float(v) => v.fmt(f),
+        }
+    }
+}
+
+macro_rules! metric_from {
+    ($variant:ident, $target:ty, $($t:ty),+) => {
+        $(
+            impl From<$t> for Metric {
+                fn from(v: $t) -> Metric {
+                    Metric::$variant(<$target>::from(v))
+                }
+            }
+        )+
+    };
+}
+
+metric_from!(Int, i64, i64, i32, i16, i8, u32, u16, u8);
+metric_from!(Double, f64, f64);
+metric_from!(Float, f32, f32);
+
+impl Event {
+    /// The time of this event, from `time_micros` if set, or else `time`.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        let micros = self
+            .time_micros
+            .or_else(|| self.time.map(|t| t.saturating_mul(1_000_000)))?;
+        let since_epoch = Duration::from_micros(micros.unsigned_abs());
+        if micros >= 0 {
+            UNIX_EPOCH.checked_add(since_epoch)
+        } else {
+            UNIX_EPOCH.checked_sub(since_epoch)
+        }
+    }
+
+    /// The time of this event as a chrono `DateTime`, see `timestamp`.
+    #[cfg(feature = "chrono")]
+    pub fn chrono_timestamp(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.timestamp().map(chrono::DateTime::from)
+    }
+
+    /// The time of this event as a time `OffsetDateTime`, see `timestamp`.
+    #[cfg(feature = "time")]
+    pub fn offset_date_time(&self) -> Option<time::OffsetDateTime> {
+        self.timestamp().map(time::OffsetDateTime::from)
+    }
+
+    /// Fill in what is missing from `defaults`. Fields set on this event
+    /// win, the metric and the time as a whole. Tags, and attributes by key,
+    /// are added unless already present.
+    pub(crate) fn merge_defaults(&mut self, defaults: &Event) {
+        fn or<T: Clone>(field: &mut Option<T>, default: &Option<T>) {
+            if field.is_none() {
+                *field = default.clone();
+            }
+        }
+
+        or(&mut self.host, &defaults.host);
+        or(&mut self.service, &defaults.service);
+        or(&mut self.state, &defaults.state);
+        or(&mut self.description, &defaults.description);
+        or(&mut self.ttl, &defaults.ttl);
+        if self.time.is_none() && self.time_micros.is_none() {
+            self.time = defaults.time;
+            self.time_micros = defaults.time_micros;
+        }
+        if self.metric().is_none() {
+            self.metric_sint64 = defaults.metric_sint64;
+            self.metric_d = defaults.metric_d;
+            self.metric_f = defaults.metric_f;
+        }
+        for tag in &defaults.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        for attribute in &defaults.attributes {
+            if !self.attributes.iter().any(|a| a.key == attribute.key) {
+                self.attributes.push(attribute.clone());
+            }
+        }
+    }
+
+    /// The metric of this event. Like riemann, `metric_sint64` takes
+    /// precedence over `metric_d`, which takes precedence over `metric_f`.
+    pub fn metric(&self) -> Option<Metric> {
+        self.metric_sint64
+            .map(Metric::Int)
+            .or_else(|| self.metric_d.map(Metric::Double))
+            .or_else(|| self.metric_f.map(Metric::Float))
+    }
+}
+
 /// Riemann event data builder
 #[derive(Default)]
 pub struct EventBuilder {
     result: Event,
+    stamp_now: bool,
 }
 
 impl EventBuilder {
@@ -16,6 +151,37 @@ impl EventBuilder {
         self
     }
 
+    /// Set both `time` and `time_micros`. `chrono::DateTime` and
+    /// `time::OffsetDateTime` convert into `SystemTime`.
+    pub fn timestamp<T: Into<SystemTime>>(mut self, timestamp: T) -> Self {
+        self.set_timestamp(timestamp.into());
+        self
+    }
+
+    /// Stamp the event with the current time when it is built, unless a time
+    /// is set by then.
+    pub fn stamp_now(mut self) -> Self {
+        self.stamp_now = true;
+        self
+    }
+
+    fn set_timestamp(&mut self, timestamp: SystemTime) {
+        let micros = match timestamp.duration_since(UNIX_EPOCH) {
+            Ok(d) => i64::try_from(d.as_micros()).unwrap_or(i64::MAX),
+            Err(e) => i64::try_from(e.duration().as_micros())
+                .map(|m| -m)
+                .unwrap_or(i64::MIN),
+        };
+        self.result.time = Some(micros.div_euclid(1_000_000));
+        self.result.time_micros = Some(micros);
+    }
+
+    /// Set the host to the name of this machine, as reported by the OS.
+    pub fn local_host(mut self) -> Self {
+        self.result.host = Some(gethostname::gethostname().to_string_lossy().into_owned());
+        self
+    }
+
     pub fn state<S: Into<String>>(mut self, state: S) -> Self {
         self.result.state = Some(state.into());
         self
@@ -51,6 +217,20 @@ impl EventBuilder {
         self
     }
 
+    /// Set the metric, in the field matching its representation, and clear
+    /// the other metric fields.
+    pub fn metric<M: Into<Metric>>(mut self, metric: M) -> Self {
+        let (sint64, d, f) = match metric.into() {
+            Metric::Int(i) => (Some(i), None, None),
+            Metric::Double(d) => (None, Some(d), None),
+            Metric::Float(f) => (None, None, Some(f)),
+        };
+        self.result.metric_sint64 = sint64;
+        self.result.metric_d = d;
+        self.result.metric_f = f;
+        self
+    }
+
     pub fn metric_sint64(mut self, metric_sint64: i64) -> Self {
         self.result.metric_sint64 = Some(metric_sint64);
         self
@@ -75,7 +255,10 @@ impl EventBuilder {
         self
     }
 
-    pub fn build(self) -> Event {
+    pub fn build(mut self) -> Event {
+        if self.stamp_now && self.result.time.is_none() && self.result.time_micros.is_none() {
+            self.set_timestamp(SystemTime::now());
+        }
         self.result
     }
 }
@@ -0,0 +1,51 @@
+//! Riemann's JSON event format, as used on its websocket and HTTP endpoints,
+//! and JSON lines of events for logging and replay.
+//!
+//! ```
+//! use rustmann::{json, EventBuilder};
+//!
+//! let event = EventBuilder::new().service("cpu").metric(0.5).build();
+//! let line = json::to_string(&event);
+//! assert_eq!(r#"{"service":"cpu","metric":0.5}"#, line);
+//! assert_eq!(event, json::from_str(&line).unwrap());
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+use crate::error::RiemannClientError;
+use crate::protos::riemann::Event;
+
+/// Serialize an event to riemann's JSON shape.
+pub fn to_string(event: &Event) -> String {
+    serde_json::to_string(event).expect("events always serialize")
+}
+
+/// Parse an event from riemann's JSON shape. Keys other than the standard
+/// event fields are custom attributes.
+pub fn from_str(s: &str) -> Result<Event, RiemannClientError> {
+    serde_json::from_str(s).map_err(|e| RiemannClientError::InvalidEvent(e.to_string()))
+}
+
+/// Write `events` to `writer`, one JSON object per line.
+pub fn write_lines<'a, W, I>(mut writer: W, events: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Event>,
+{
+    for event in events {
+        serde_json::to_writer(&mut writer, event)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Read events written by `write_lines`. Blank lines are skipped.
+pub fn read_lines<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<Event, RiemannClientError>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(ref line) if line.trim().is_empty() => None,
+        Ok(line) => Some(from_str(&line)),
+        Err(e) => Some(Err(RiemannClientError::from(e))),
+    })
+}
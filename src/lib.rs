@@ -8,11 +8,21 @@
 //!
 //! ## Features
 //!
-//! * Full async-await API
-//! * TCP/UDP/TLS transport support
-//! * Auto reconnect
+//! * Full async-await API, and a blocking client for synchronous programs
+//! * TCP/UDP/TLS/Unix domain socket transport support
+//! * Auto reconnect, with failover across multiple endpoints
+//! * Configurable retry policy
+//! * Connection pooling and request pipelining
+//! * Disk spool for events while riemann is unreachable
+//! * Options from connection URLs, environment variables or config files
 //! * Send and query API
+//! * Live query subscriptions over websocket (`websocket` feature)
+//! * Background batching with bounded queue
+//! * Consistent-hash sharding across several servers
+//! * Replicated writes to several servers
 //! * EventBuilder
+//! * Riemann JSON event format and JSON lines (`json` feature), and
+//!   `Serialize`/`Deserialize` for `Event` in that format (`serde` feature)
 //! * A usable Cli in example
 //!
 //! ## Quick Start
@@ -23,7 +33,7 @@
 //! #[tokio::main]
 //! async fn main() -> Result<(), RiemannClientError> {
 //!     // create client with default configuration (to localhost:5555)
-//!     let mut client = RiemannClient::new(&RiemannClientOptions::default());
+//!     let client = RiemannClient::new(&RiemannClientOptions::default());
 //!
 //!     // create a riemann event using evnet builder API
 //!     let event = EventBuilder::new()
@@ -33,8 +43,7 @@
 //!         .build();
 //!
 //!     // send event to server
-//!     let response = client.send_events(vec![event]).await?;
-//!     println!("{:?}", response);
+//!     client.send_events(vec![event]).await?;
 //!
 //!     // query riemann
 //!     let query_response = client.send_query("service = \"riemann_test\"").await?;
@@ -47,25 +56,60 @@
 //! more usage demo.
 //!
 
+mod batch;
+pub mod blocking;
 mod client;
 mod codec;
+mod config;
 mod error;
 mod event;
+#[cfg(feature = "serde")]
+mod iso8601;
+#[cfg(feature = "json")]
+pub mod json;
 mod options;
+mod pool;
 pub mod protos {
     pub mod riemann {
         include!(concat!(env!("OUT_DIR"), "/riemann.rs"));
     }
 }
+mod replicate;
+mod retry;
+#[cfg(feature = "serde")]
+mod serialize;
+mod shard;
+mod spool;
 mod state;
+#[cfg(feature = "websocket")]
+mod subscribe;
 #[cfg(feature = "tls")]
 mod tls;
 mod transport;
+mod uri;
+mod validate;
 
-pub use crate::client::RiemannClient;
-pub use crate::error::RiemannClientError;
-pub use crate::event::EventBuilder;
-pub use crate::options::{RiemannClientOptions, RiemannClientOptionsBuilder};
+pub use crate::batch::{
+    BatchOptions, BatchOptionsBuilder, BatchSender, FlushReport, FlushReports, OverflowPolicy,
+};
+pub use crate::client::{RiemannClient, ShutdownReport};
+pub use crate::config::RiemannClientConfig;
+pub use crate::error::{
+    BatchError, InvalidField, Operation, ReplicaFailure, ReplicationError, RiemannClientError,
+    ValidationError,
+};
+pub use crate::event::{EventBuilder, Metric};
+pub use crate::options::{Endpoint, Protocol, RiemannClientOptions, RiemannClientOptionsBuilder};
+pub use crate::pool::PoolSelection;
+pub use crate::replicate::{ReplicatedClient, ReplicationPolicy, ReplicationReport};
+pub use crate::retry::RetryPolicy;
+pub use crate::shard::{ShardField, ShardResult, ShardedClient};
+pub use crate::spool::{Spool, SpoolDropPolicy, SpoolOptions, SpoolOptionsBuilder};
+#[cfg(feature = "websocket")]
+pub use crate::subscribe::Subscription;
+pub use crate::validate::{Validation, MAX_DESCRIPTION_LEN};
 
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsPin;
 #[cfg(feature = "tls")]
 pub use tokio_rustls::rustls::ClientConfig;
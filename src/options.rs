@@ -1,10 +1,87 @@
 #[cfg(feature = "tls")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(feature = "tls")]
 use std::sync::Arc;
 
 use derive_builder::Builder;
 use getset::Getters;
 #[cfg(feature = "tls")]
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use rustls_pki_types::ServerName;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tls")]
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::error::RiemannClientError;
+use crate::pool::PoolSelection;
+use crate::protos::riemann::Event;
+#[cfg(feature = "tls")]
+use crate::tls::{TlsPin, TlsSettings, TlsSource};
+use crate::validate::Validation;
+
+/// A riemann server address
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Getters)]
+#[get = "pub"]
+pub struct Endpoint {
+    host: String,
+    port: u16,
+}
+
+impl Endpoint {
+    pub fn new<S: Into<String>>(host: S, port: u16) -> Endpoint {
+        Endpoint {
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+impl<S: Into<String>> From<(S, u16)> for Endpoint {
+    fn from((host, port): (S, u16)) -> Endpoint {
+        Endpoint::new(host, port)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// Transport to the riemann server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Tls,
+    /// Unix domain socket
+    Unix,
+}
+
+impl FromStr for Protocol {
+    type Err = RiemannClientError;
+
+    fn from_str(s: &str) -> Result<Protocol, RiemannClientError> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            "tls" => Ok(Protocol::Tls),
+            "unix" => Ok(Protocol::Unix),
+            _ => Err(RiemannClientError::InvalidOptions(format!(
+                "unknown protocol {}",
+                s
+            ))),
+        }
+    }
+}
 
 /// Riemann connection options
 #[derive(Builder, Clone, Getters)]
@@ -15,27 +92,156 @@ use tokio_rustls::rustls::{ClientConfig, RootCertStore};
 pub struct RiemannClientOptions {
     host: String,
     port: u16,
+    /// Ordered list of servers to connect to. The first one is the primary,
+    /// the others are tried in turn when the active one is unreachable. When
+    /// not set, `host` and `port` are the only endpoint. When set, `host` and
+    /// `port` reflect the primary.
+    #[builder(setter(each(name = "endpoint", into)))]
+    endpoints: Vec<Endpoint>,
+    /// How long to stay on a fallback endpoint before trying the primary
+    /// again. `0` disables failing back.
+    failback_interval_ms: u64,
     connect_timeout_ms: u64,
     socket_timeout_ms: u64,
+    /// Maximum number of requests waiting for a response on one connection
+    max_in_flight: usize,
+    /// Number of connections to keep to the server
+    pool_size: usize,
+    pool_selection: PoolSelection,
+    /// Open all pooled connections when the client is created, instead of on
+    /// first use
+    pool_warm_up: bool,
+    /// Delay before the second reconnect attempt, doubled for each further
+    /// failed attempt, with random jitter. `0` reconnects right away.
+    reconnect_backoff_ms: u64,
+    /// Upper bound of the reconnect delay
+    reconnect_backoff_max_ms: u64,
+    /// Open the circuit breaker after this many consecutive failed connection
+    /// attempts, `0`, the default, disables it. Each pooled connection has a
+    /// breaker of its own, so with `pool_size` n it takes up to n times this
+    /// many failed attempts before every call fails fast.
+    circuit_breaker_threshold: u32,
+    /// How long an open circuit breaker fails calls before letting a single
+    /// connection attempt through
+    circuit_breaker_cooldown_ms: u64,
     use_udp: bool,
+    /// Template merged into every event sent: fields already set on the
+    /// event win, tags and attributes are added unless already present
+    #[builder(setter(into, strip_option))]
+    event_defaults: Option<Event>,
+    /// What to do with invalid events before sending them, after merging
+    /// `event_defaults`
+    validation: Validation,
+    /// Largest UDP datagram to send. Batches are split over several
+    /// datagrams to stay within it.
+    udp_max_datagram_size: usize,
+    /// Connect to a riemann server listening on this unix domain socket
+    /// instead of `host` and `port`. Cannot be combined with `use_tls`,
+    /// `use_udp` or several endpoints.
+    #[cfg(unix)]
+    #[builder(setter(into, strip_option))]
+    unix_socket_path: Option<PathBuf>,
+    /// Port of riemann's websocket server, used by subscriptions
+    #[cfg(feature = "websocket")]
+    ws_port: u16,
     #[cfg(feature = "tls")]
     use_tls: bool,
+    /// TLS config as built. `None` for options built with `build` from PEM
+    /// files, which are loaded on first connect. `RiemannClient::reload_tls`
+    /// and `RiemannClient::set_tls_config` replace the config in use.
     #[cfg(feature = "tls")]
     tls_config: Option<Arc<ClientConfig>>,
-}
-
-#[cfg(feature = "tls")]
-fn default_tls_config() -> Arc<ClientConfig> {
-    let root_store = RootCertStore {
-        roots: webpki_roots::TLS_SERVER_ROOTS.iter().cloned().collect(),
-    };
-    let tls_config = ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
-    Arc::new(tls_config)
+    #[cfg(feature = "tls")]
+    #[builder(setter(skip))]
+    #[get = "pub(crate)"]
+    tls_source: Option<Arc<TlsSource>>,
+    /// How often to check the PEM files for changes, and load them again
+    /// when they changed. `0` disables the check.
+    #[cfg(feature = "tls")]
+    tls_reload_interval_ms: u64,
+    /// PEM bundle of the CA certificates to trust, instead of the webpki
+    /// roots
+    #[cfg(feature = "tls")]
+    #[builder(setter(into, strip_option))]
+    tls_ca_file: Option<PathBuf>,
+    /// PEM certificate chain to authenticate with, for mutual TLS
+    #[cfg(feature = "tls")]
+    #[builder(setter(into, strip_option))]
+    tls_cert_file: Option<PathBuf>,
+    /// PEM private key of `tls_cert_file`, in PKCS#8, RSA or SEC1 format
+    #[cfg(feature = "tls")]
+    #[builder(setter(into, strip_option))]
+    tls_key_file: Option<PathBuf>,
+    /// Trust only a server certificate matching one of these fingerprints,
+    /// instead of checking it against CA certificates and the server name
+    #[cfg(feature = "tls")]
+    #[builder(setter(each(name = "tls_pin")))]
+    tls_pins: Vec<TlsPin>,
+    /// Name to send in SNI and to verify the server certificate against,
+    /// instead of the endpoint host
+    #[cfg(feature = "tls")]
+    #[builder(setter(into, strip_option))]
+    tls_server_name: Option<String>,
+    /// Why the options are invalid, when built with `build`. Every
+    /// connection attempt fails with it.
+    #[builder(setter(skip))]
+    #[get = "pub(crate)"]
+    invalid: Option<String>,
 }
 
 impl RiemannClientOptionsBuilder {
+    /// Switch to `protocol`, turning the other transports off. TLS defaults
+    /// to port 5554. `unix_socket_path` is required for `Protocol::Unix`, and
+    /// ignored otherwise.
+    pub(crate) fn set_protocol(
+        mut self,
+        protocol: Protocol,
+        unix_socket_path: Option<PathBuf>,
+    ) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+        self.use_udp = Some(protocol == Protocol::Udp);
+        #[cfg(feature = "tls")]
+        {
+            self.use_tls = Some(protocol == Protocol::Tls);
+            if protocol == Protocol::Tls && self.port.is_none() {
+                self.port = Some(5554);
+            }
+        }
+        #[cfg(not(feature = "tls"))]
+        {
+            if protocol == Protocol::Tls {
+                return Err(RiemannClientError::InvalidOptions(
+                    "TLS requires the tls feature".to_owned(),
+                ));
+            }
+        }
+
+        #[cfg(unix)]
+        {
+            self.unix_socket_path = match protocol {
+                Protocol::Unix => match unix_socket_path {
+                    Some(path) => Some(Some(path)),
+                    None => {
+                        return Err(RiemannClientError::InvalidOptions(
+                            "unix protocol requires a socket path".to_owned(),
+                        ))
+                    }
+                },
+                _ => None,
+            };
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = unix_socket_path;
+            if protocol == Protocol::Unix {
+                return Err(RiemannClientError::InvalidOptions(
+                    "unix sockets are not supported on this platform".to_owned(),
+                ));
+            }
+        }
+
+        Ok(self)
+    }
+
     #[cfg(feature = "tls")]
     fn tls_enabled(&self) -> bool {
         self.use_tls.unwrap_or(false)
@@ -47,18 +253,95 @@ impl RiemannClientOptionsBuilder {
         false
     }
 
-    #[cfg(feature = "tls")]
-    fn get_tls_config(&self) -> Option<Arc<ClientConfig>> {
-        self.tls_config.as_ref().cloned().unwrap_or_else(|| {
-            if self.tls_enabled() {
-                Some(default_tls_config())
-            } else {
-                None
+    /// Check that the options fit together.
+    fn validate(&self) -> Result<(), String> {
+        #[cfg(unix)]
+        {
+            if let Some(Some(_)) = self.unix_socket_path {
+                if self.tls_enabled() || self.use_udp == Some(true) {
+                    return Err(
+                        "unix_socket_path cannot be combined with use_tls or use_udp".to_owned(),
+                    );
+                }
+                if self.endpoints.as_ref().map_or(0, Vec::len) > 1 {
+                    return Err(
+                        "unix_socket_path cannot be combined with several endpoints".to_owned()
+                    );
+                }
             }
-        })
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            if let Some(Some(ref name)) = self.tls_server_name {
+                ServerName::try_from(name.as_str())
+                    .map_err(|_| format!("invalid tls_server_name {}", name))?;
+            }
+
+            if !self.tls_settings().is_empty() {
+                if self.tls_config.is_some() {
+                    return Err("tls_config cannot be combined with PEM files or pins".to_owned());
+                }
+                if !self.tls_enabled() {
+                    return Err("PEM files or pins are set but use_tls is not".to_owned());
+                }
+            }
+        }
+        Ok(())
     }
 
+    #[cfg(feature = "tls")]
+    fn tls_settings(&self) -> TlsSettings {
+        TlsSettings {
+            ca_file: self.tls_ca_file.clone().flatten(),
+            cert_file: self.tls_cert_file.clone().flatten(),
+            key_file: self.tls_key_file.clone().flatten(),
+            pins: self.tls_pins.clone().unwrap_or_default(),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn get_tls_source(&self) -> Option<Arc<TlsSource>> {
+        if let Some(ref tls_config) = self.tls_config {
+            return tls_config
+                .clone()
+                .map(|config| Arc::new(TlsSource::new(config)));
+        }
+        if !self.tls_enabled() {
+            return None;
+        }
+        Some(Arc::new(TlsSource::from_settings(self.tls_settings())))
+    }
+
+    /// Build the options. Unlike `try_build`, this never fails: the TLS
+    /// certificate and key files are loaded on first connect, and invalid
+    /// options make every connection attempt fail with
+    /// `RiemannClientError::InvalidOptions`.
     pub fn build(self) -> RiemannClientOptions {
+        let invalid = self.validate().err();
+        self.assemble(invalid)
+    }
+
+    /// Build the options, failing with `RiemannClientError::InvalidOptions`
+    /// when they do not fit together or the TLS certificate or key files
+    /// cannot be loaded.
+    pub fn try_build(self) -> Result<RiemannClientOptions, RiemannClientError> {
+        self.validate()
+            .map_err(RiemannClientError::InvalidOptions)?;
+        #[allow(unused_mut)]
+        let mut options = self.assemble(None);
+        #[cfg(feature = "tls")]
+        {
+            if let Some(ref source) = options.tls_source {
+                options.tls_config = Some(source.current()?.0);
+            }
+        }
+        Ok(options)
+    }
+
+    fn assemble(self, invalid: Option<String>) -> RiemannClientOptions {
+        #[cfg(feature = "tls")]
+        let tls_source = self.get_tls_source();
         let use_tls = self.tls_enabled();
         let udp = if use_tls {
             false
@@ -66,16 +349,56 @@ impl RiemannClientOptionsBuilder {
             self.use_udp.unwrap_or(false)
         };
 
+        let endpoints = match self.endpoints {
+            Some(ref endpoints) if !endpoints.is_empty() => endpoints.clone(),
+            _ => vec![Endpoint::new(
+                self.host.clone().unwrap_or_else(|| "127.0.0.1".to_owned()),
+                self.port.unwrap_or(5555),
+            )],
+        };
+
         RiemannClientOptions {
-            host: self.host.clone().unwrap_or_else(|| "127.0.0.1".to_owned()),
-            port: self.port.unwrap_or(5555),
+            host: endpoints[0].host.clone(),
+            port: endpoints[0].port,
+            endpoints,
+            failback_interval_ms: self.failback_interval_ms.unwrap_or(30000),
             connect_timeout_ms: self.connect_timeout_ms.unwrap_or(2000),
-            socket_timeout_ms: self.connect_timeout_ms.unwrap_or(3000),
+            socket_timeout_ms: self.socket_timeout_ms.unwrap_or(3000),
+            max_in_flight: self.max_in_flight.unwrap_or(128),
+            pool_size: self.pool_size.unwrap_or(1).max(1),
+            pool_selection: self.pool_selection.unwrap_or(PoolSelection::RoundRobin),
+            pool_warm_up: self.pool_warm_up.unwrap_or(false),
+            reconnect_backoff_ms: self.reconnect_backoff_ms.unwrap_or(100),
+            reconnect_backoff_max_ms: self.reconnect_backoff_max_ms.unwrap_or(10000),
+            circuit_breaker_threshold: self.circuit_breaker_threshold.unwrap_or(0),
+            circuit_breaker_cooldown_ms: self.circuit_breaker_cooldown_ms.unwrap_or(10000),
             use_udp: udp,
+            event_defaults: self.event_defaults.clone().flatten(),
+            validation: self.validation.unwrap_or(Validation::Off),
+            udp_max_datagram_size: self.udp_max_datagram_size.unwrap_or(16384),
+            #[cfg(unix)]
+            unix_socket_path: self.unix_socket_path.clone().flatten(),
+            #[cfg(feature = "websocket")]
+            ws_port: self.ws_port.unwrap_or(5556),
             #[cfg(feature = "tls")]
             use_tls,
             #[cfg(feature = "tls")]
-            tls_config: self.get_tls_config(),
+            tls_config: self.tls_config.clone().flatten(),
+            #[cfg(feature = "tls")]
+            tls_source,
+            #[cfg(feature = "tls")]
+            tls_reload_interval_ms: self.tls_reload_interval_ms.unwrap_or(0),
+            #[cfg(feature = "tls")]
+            tls_ca_file: self.tls_ca_file.flatten(),
+            #[cfg(feature = "tls")]
+            tls_cert_file: self.tls_cert_file.flatten(),
+            #[cfg(feature = "tls")]
+            tls_key_file: self.tls_key_file.flatten(),
+            #[cfg(feature = "tls")]
+            tls_pins: self.tls_pins.unwrap_or_default(),
+            #[cfg(feature = "tls")]
+            tls_server_name: self.tls_server_name.flatten(),
+            invalid,
         }
     }
 }
@@ -85,19 +408,62 @@ impl Default for RiemannClientOptions {
         Self {
             host: "127.0.0.1".to_owned(),
             port: 5555,
+            endpoints: vec![Endpoint::new("127.0.0.1", 5555)],
+            failback_interval_ms: 30000,
             connect_timeout_ms: 2000,
             socket_timeout_ms: 3000,
+            max_in_flight: 128,
+            pool_size: 1,
+            pool_selection: PoolSelection::RoundRobin,
+            pool_warm_up: false,
+            reconnect_backoff_ms: 100,
+            reconnect_backoff_max_ms: 10000,
+            circuit_breaker_threshold: 0,
+            circuit_breaker_cooldown_ms: 10000,
             use_udp: false,
+            event_defaults: None,
+            validation: Validation::Off,
+            udp_max_datagram_size: 16384,
+            #[cfg(unix)]
+            unix_socket_path: None,
+            #[cfg(feature = "websocket")]
+            ws_port: 5556,
             #[cfg(feature = "tls")]
             use_tls: false,
             #[cfg(feature = "tls")]
             tls_config: None,
+            #[cfg(feature = "tls")]
+            tls_source: None,
+            #[cfg(feature = "tls")]
+            tls_reload_interval_ms: 0,
+            #[cfg(feature = "tls")]
+            tls_ca_file: None,
+            #[cfg(feature = "tls")]
+            tls_cert_file: None,
+            #[cfg(feature = "tls")]
+            tls_key_file: None,
+            #[cfg(feature = "tls")]
+            tls_pins: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls_server_name: None,
+            invalid: None,
         }
     }
 }
 
 impl RiemannClientOptions {
-    pub(crate) fn to_socket_addr_string(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+    /// A copy of these options with a TLS source of its own, so that
+    /// replacing the TLS config of one client leaves the others alone.
+    pub(crate) fn for_client(&self) -> RiemannClientOptions {
+        #[allow(unused_mut)]
+        let mut options = self.clone();
+        #[cfg(feature = "tls")]
+        {
+            options.tls_source = self
+                .tls_source
+                .as_ref()
+                .map(|source| Arc::new(source.fork()));
+        }
+        options
     }
 }
@@ -0,0 +1,141 @@
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use futures::lock::Mutex;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+use crate::error::RiemannClientError;
+use crate::options::RiemannClientOptions;
+use crate::state::{ClientState, Inner};
+use crate::transport::Transport;
+
+/// How a pooled client picks a connection for each call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSelection {
+    RoundRobin,
+    /// The connection with the fewest calls in progress
+    LeastLoaded,
+}
+
+/// One connection of the pool, with its own connection state, so a broken
+/// connection is replaced without touching the others.
+pub(crate) struct PoolSlot {
+    inner: Mutex<Inner>,
+    in_flight: AtomicUsize,
+}
+
+impl PoolSlot {
+    pub(crate) async fn connection(&self) -> Result<Arc<Transport>, RiemannClientError> {
+        let mut inner = self.inner.lock().await;
+        let i = inner.deref_mut();
+        i.await
+    }
+
+    /// Drop a broken connection so the next call reconnects, unless another
+    /// call has already replaced it.
+    pub(crate) async fn reset(&self, conn: &Arc<Transport>) {
+        let mut inner = self.inner.lock().await;
+        if let ClientState::Connected(ref current) = inner.state {
+            if Arc::ptr_eq(current, conn) {
+                inner.state = ClientState::Disconnected;
+            }
+        }
+    }
+}
+
+/// Marks a call in progress on a slot until dropped.
+pub(crate) struct SlotGuard<'a> {
+    slot: &'a PoolSlot,
+}
+
+impl<'a> std::ops::Deref for SlotGuard<'a> {
+    type Target = PoolSlot;
+
+    fn deref(&self) -> &PoolSlot {
+        self.slot
+    }
+}
+
+impl<'a> Drop for SlotGuard<'a> {
+    fn drop(&mut self) {
+        self.slot.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct Pool {
+    slots: Vec<PoolSlot>,
+    next: AtomicUsize,
+    selection: PoolSelection,
+    /// notified whenever a connection is made
+    connected: Arc<Notify>,
+}
+
+impl Pool {
+    pub(crate) fn new(options: &RiemannClientOptions, active_endpoint: Arc<AtomicUsize>) -> Pool {
+        let connected = Arc::new(Notify::new());
+        let slots = (0..(*options.pool_size()).max(1))
+            .map(|_| PoolSlot {
+                inner: Mutex::new(Inner::new(
+                    options,
+                    active_endpoint.clone(),
+                    connected.clone(),
+                )),
+                in_flight: AtomicUsize::new(0),
+            })
+            .collect();
+
+        Pool {
+            slots,
+            next: AtomicUsize::new(0),
+            selection: *options.pool_selection(),
+            connected,
+        }
+    }
+
+    pub(crate) fn connected(&self) -> &Notify {
+        &self.connected
+    }
+
+    pub(crate) fn checkout(&self) -> SlotGuard<'_> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let n = self.slots.len();
+
+        let idx = match self.selection {
+            PoolSelection::RoundRobin => start % n,
+            // scan from the round robin position so ties are spread evenly
+            PoolSelection::LeastLoaded => (0..n)
+                .map(|i| (start + i) % n)
+                .min_by_key(|i| self.slots[*i].in_flight.load(Ordering::Relaxed))
+                .unwrap_or(0),
+        };
+
+        let slot = &self.slots[idx];
+        slot.in_flight.fetch_add(1, Ordering::Relaxed);
+        SlotGuard { slot }
+    }
+
+    /// Close every connection. A connection still used by a call is left to
+    /// close when that call is done.
+    pub(crate) async fn close(&self, socket_timeout: Duration) {
+        let closing = self.slots.iter().map(|slot| async move {
+            let conn = slot.inner.lock().await.close();
+            if let Some(conn) = conn.and_then(|conn| Arc::try_unwrap(conn).ok()) {
+                let _ = timeout(socket_timeout, conn.close()).await;
+            }
+        });
+        join_all(closing).await;
+    }
+
+    /// Connect every slot that is not connected yet.
+    pub(crate) async fn warm_up(&self) -> Result<(), RiemannClientError> {
+        let results = join_all(self.slots.iter().map(|slot| slot.connection())).await;
+        for r in results {
+            r?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use futures::future::join_all;
+use getset::Getters;
+
+use crate::client::RiemannClient;
+use crate::error::{ReplicaFailure, ReplicationError, RiemannClientError};
+use crate::protos::riemann::Event;
+
+/// How many replicas must acknowledge a send for it to succeed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationPolicy {
+    All,
+    Any,
+    /// A strict majority of replicas
+    Quorum,
+}
+
+impl ReplicationPolicy {
+    fn required(self, replicas: usize) -> usize {
+        match self {
+            ReplicationPolicy::All => replicas,
+            ReplicationPolicy::Any => 1,
+            ReplicationPolicy::Quorum => replicas / 2 + 1,
+        }
+    }
+}
+
+/// Outcome of a `ReplicatedClient::send_events` that satisfied the policy
+#[derive(Debug, Getters)]
+#[get = "pub"]
+pub struct ReplicationReport {
+    /// Replicas that failed, which the policy could tolerate
+    failures: Vec<ReplicaFailure>,
+    acknowledged: usize,
+}
+
+impl ReplicationReport {
+    /// Whether every replica acknowledged.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// A client that writes every batch to several riemann servers, for example
+/// the old and new cluster during a migration.
+///
+/// Queries are only sent to the primary replica.
+///
+/// ```no_run
+/// use rustmann::{ReplicatedClient, ReplicationPolicy, RiemannClient, RiemannClientOptionsBuilder};
+///
+/// let old = RiemannClientOptionsBuilder::default().host("riemann-old").build();
+/// let new = RiemannClientOptionsBuilder::default().host("riemann-new").build();
+///
+/// let mut client =
+///     ReplicatedClient::new("old", RiemannClient::new(&old), ReplicationPolicy::All);
+/// client.add_replica("new", RiemannClient::new(&new));
+/// ```
+pub struct ReplicatedClient {
+    replicas: Vec<(String, RiemannClient)>,
+    policy: ReplicationPolicy,
+}
+
+impl ReplicatedClient {
+    /// Create a replicated client with `client` as its primary.
+    pub fn new<S: Into<String>>(
+        name: S,
+        client: RiemannClient,
+        policy: ReplicationPolicy,
+    ) -> ReplicatedClient {
+        ReplicatedClient {
+            replicas: vec![(name.into(), client)],
+            policy,
+        }
+    }
+
+    /// Add a secondary replica.
+    pub fn add_replica<S: Into<String>>(&mut self, name: S, client: RiemannClient) {
+        self.replicas.push((name.into(), client));
+    }
+
+    /// Name of the primary replica.
+    pub fn primary(&self) -> &str {
+        &self.replicas[0].0
+    }
+
+    /// Names of all replicas, primary first.
+    pub fn replicas(&self) -> impl Iterator<Item = &str> {
+        self.replicas.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Send events to all replicas concurrently. Succeeds when the policy is
+    /// satisfied. Either way, every replica that failed is reported.
+    pub async fn send_events(
+        &self,
+        events: Vec<Event>,
+    ) -> Result<ReplicationReport, ReplicationError> {
+        let sends = self.replicas.iter().map(|(name, client)| {
+            let events = events.clone();
+            async move { (name, client.send_events(events).await) }
+        });
+        let results = join_all(sends).await;
+
+        let required = self.policy.required(self.replicas.len());
+        let mut acknowledged = 0;
+        let mut failures = Vec::new();
+        for (name, result) in results {
+            match result {
+                Ok(()) => acknowledged += 1,
+                Err(e) => failures.push(ReplicaFailure::new(name.clone(), e)),
+            }
+        }
+
+        if acknowledged >= required {
+            Ok(ReplicationReport {
+                failures,
+                acknowledged,
+            })
+        } else {
+            Err(ReplicationError::new(failures, acknowledged, required))
+        }
+    }
+
+    /// Query the primary replica.
+    pub async fn send_query<S>(&self, query_string: S) -> Result<Vec<Event>, RiemannClientError>
+    where
+        S: AsRef<str>,
+    {
+        self.replicas[0].1.send_query(query_string).await
+    }
+}
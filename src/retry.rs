@@ -0,0 +1,118 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::RiemannClientError;
+
+type RetryPredicate = dyn Fn(&RiemannClientError) -> bool + Send + Sync;
+
+/// Decides whether and when `RiemannClient` retries a failed call.
+///
+/// Retries happen over a fresh connection when the previous one broke, and
+/// over the same one after a response timeout, waiting an exponentially
+/// growing, jittered delay between attempts. Errors reported by the riemann server,
+/// or caused by a request that can never be sent, are never retried,
+/// whatever the predicate says. Neither are calls on a closed client.
+///
+/// ```
+/// use rustmann::{RetryPolicy, RiemannClient, RiemannClientOptions};
+///
+/// let client = RiemannClient::new(&RiemannClientOptions::default())
+///     .with_retry_policy(RetryPolicy::new(5).backoff_ms(50));
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff_ms: u64,
+    backoff_max_ms: u64,
+    retry_if: Arc<RetryPredicate>,
+}
+
+impl RetryPolicy {
+    /// A policy that makes at most `max_attempts` attempts, including the
+    /// first one, and retries the errors that are
+    /// `RiemannClientError::is_retryable`.
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff_ms: 100,
+            backoff_max_ms: 2000,
+            retry_if: Arc::new(RiemannClientError::is_retryable),
+        }
+    }
+
+    /// A policy that never retries. This is the default of `RiemannClient`.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+
+    /// Delay before the first retry, doubled for each further retry.
+    pub fn backoff_ms(mut self, backoff_ms: u64) -> RetryPolicy {
+        self.backoff_ms = backoff_ms;
+        self
+    }
+
+    /// Upper bound of the delay between retries.
+    pub fn backoff_max_ms(mut self, backoff_max_ms: u64) -> RetryPolicy {
+        self.backoff_max_ms = backoff_max_ms;
+        self
+    }
+
+    /// Retry only errors accepted by `predicate`.
+    pub fn retry_if<F>(mut self, predicate: F) -> RetryPolicy
+    where
+        F: Fn(&RiemannClientError) -> bool + Send + Sync + 'static,
+    {
+        self.retry_if = Arc::new(predicate);
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether a call that failed with `error` on its `attempt`th try (counted
+    /// from 1) should be tried again.
+    pub(crate) fn should_retry(&self, error: &RiemannClientError, attempt: u32) -> bool {
+        attempt < self.max_attempts
+            && !error.is_request_error()
+            && !matches!(error, RiemannClientError::Closed)
+            && (self.retry_if)(error)
+    }
+
+    /// Delay before retrying after the `attempt`th try.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        backoff(self.backoff_ms, self.backoff_max_ms, attempt)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Never retry, like `RetryPolicy::none` and the default of
+    /// `RiemannClient`.
+    fn default() -> RetryPolicy {
+        RetryPolicy::none()
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff_ms", &self.backoff_ms)
+            .field("backoff_max_ms", &self.backoff_max_ms)
+            .finish()
+    }
+}
+
+/// Exponential backoff with equal jitter: a random delay between half and
+/// all of `base_ms * 2^(n - 1)`, capped at `max_ms`.
+pub(crate) fn backoff(base_ms: u64, max_ms: u64, n: u32) -> Duration {
+    let exp = n.saturating_sub(1).min(32);
+    let delay = base_ms.saturating_mul(1 << exp).min(max_ms);
+
+    let half = delay / 2;
+    let jitter = RandomState::new().build_hasher().finish() % (half + 1);
+    Duration::from_millis(delay - half + jitter)
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::event::Metric;
+use crate::iso8601;
+use crate::protos::riemann::{Attribute, Event};
+
+/// Events serialize to the JSON shape riemann uses on its websocket and HTTP
+/// endpoints: a single `metric`, `time` as an ISO 8601 string, and custom
+/// attributes as top-level keys.
+///
+/// A deserialized event has both `time` and `time_micros` set, and a float
+/// metric comes back as `metric_d`.
+///
+/// Only `Event` implements serde's traits; the other protobuf types, such as
+/// `Msg`, `Query` and `Attribute`, have no JSON form in riemann.
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        let fields = [
+            ("host", &self.host),
+            ("service", &self.service),
+            ("state", &self.state),
+            ("description", &self.description),
+        ];
+        for (key, value) in fields.iter() {
+            if let Some(value) = value {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        match self.metric() {
+            Some(Metric::Int(i)) => map.serialize_entry("metric", &i)?,
+            Some(Metric::Double(d)) => map.serialize_entry("metric", &d)?,
+            Some(Metric::Float(f)) => map.serialize_entry("metric", &f64::from(f))?,
+            None => {}
+        }
+        if !self.tags.is_empty() {
+            map.serialize_entry("tags", &self.tags)?;
+        }
+        let micros = self
+            .time_micros
+            .or_else(|| self.time.map(|t| t.saturating_mul(1_000_000)));
+        if let Some(micros) = micros {
+            map.serialize_entry("time", &iso8601::format_micros(micros))?;
+        }
+        if let Some(ttl) = self.ttl {
+            map.serialize_entry("ttl", &ttl)?;
+        }
+        for attribute in &self.attributes {
+            map.serialize_entry(&attribute.key, &attribute.value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Event, D::Error> {
+        deserializer.deserialize_map(EventVisitor)
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Time {
+    Iso8601(String),
+    /// seconds since the unix epoch
+    Seconds(f64),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum AttributeValue {
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+struct EventVisitor;
+
+impl<'de> Visitor<'de> for EventVisitor {
+    type Value = Event;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a riemann event")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Event, A::Error> {
+        let mut event = Event::default();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "host" => event.host = map.next_value()?,
+                "service" => event.service = map.next_value()?,
+                "state" => event.state = map.next_value()?,
+                "description" => event.description = map.next_value()?,
+                "ttl" => event.ttl = map.next_value()?,
+                "metric" => match map.next_value()? {
+                    Some(Number::Int(i)) => event.metric_sint64 = Some(i),
+                    Some(Number::Float(d)) => event.metric_d = Some(d),
+                    None => {}
+                },
+                "time" => {
+                    let micros = match map.next_value()? {
+                        Some(Time::Iso8601(s)) => iso8601::parse_micros(&s)
+                            .ok_or_else(|| de::Error::custom(format!("bad time {:?}", s)))?,
+                        Some(Time::Seconds(secs)) => (secs * 1_000_000.0) as i64,
+                        None => continue,
+                    };
+                    event.time = Some(micros.div_euclid(1_000_000));
+                    event.time_micros = Some(micros);
+                }
+                "tags" => event.tags = map.next_value::<Option<Vec<String>>>()?.unwrap_or_default(),
+                _ => {
+                    let value = map
+                        .next_value::<Option<AttributeValue>>()?
+                        .map(|v| match v {
+                            AttributeValue::String(s) => s,
+                            AttributeValue::Bool(b) => b.to_string(),
+                            AttributeValue::Int(i) => i.to_string(),
+                            AttributeValue::Float(d) => d.to_string(),
+                        });
+                    event.attributes.push(Attribute { key, value });
+                }
+            }
+        }
+
+        Ok(event)
+    }
+}
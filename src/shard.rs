@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use futures::future::join_all;
+use getset::Getters;
+
+use crate::client::RiemannClient;
+use crate::error::RiemannClientError;
+use crate::protos::riemann::Event;
+
+/// Number of points each shard owns on the hash ring
+const VIRTUAL_NODES: usize = 160;
+
+/// Event field that makes up the shard key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardField {
+    Host,
+    Service,
+    State,
+    /// Value of the attribute with given key
+    Attribute(String),
+}
+
+impl ShardField {
+    fn value<'a>(&self, event: &'a Event) -> Option<&'a str> {
+        match self {
+            ShardField::Host => event.host.as_deref(),
+            ShardField::Service => event.service.as_deref(),
+            ShardField::State => event.state.as_deref(),
+            ShardField::Attribute(key) => event
+                .attributes
+                .iter()
+                .find(|a| &a.key == key)
+                .and_then(|a| a.value.as_deref()),
+        }
+    }
+}
+
+/// Outcome of sending one shard's part of a batch
+#[derive(Debug, Getters)]
+#[get = "pub"]
+pub struct ShardResult {
+    /// Name of the shard
+    shard: String,
+    /// Number of events routed to this shard
+    events: usize,
+    result: Result<(), RiemannClientError>,
+}
+
+impl ShardResult {
+    pub fn into_result(self) -> Result<(), RiemannClientError> {
+        self.result
+    }
+}
+
+/// A client that partitions events across several riemann servers.
+///
+/// Each event is routed by a consistent hash of the configured fields, so a
+/// series always lands on the same server, and adding a shard only moves
+/// about `1/n` of the series.
+///
+/// ```no_run
+/// use rustmann::{RiemannClient, RiemannClientOptionsBuilder, ShardField, ShardedClient};
+///
+/// let mut client = ShardedClient::new(vec![ShardField::Host, ShardField::Service]);
+/// for host in &["riemann-1", "riemann-2"] {
+///     let options = RiemannClientOptionsBuilder::default().host(*host).build();
+///     client.add_shard(*host, RiemannClient::new(&options));
+/// }
+/// ```
+pub struct ShardedClient {
+    fields: Vec<ShardField>,
+    shards: Vec<(String, RiemannClient)>,
+    ring: BTreeMap<u64, usize>,
+}
+
+impl ShardedClient {
+    /// Create a sharded client keyed by `fields`, with no shard yet.
+    pub fn new(fields: Vec<ShardField>) -> ShardedClient {
+        ShardedClient {
+            fields,
+            shards: Vec::new(),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    /// Add a backend. The name identifies the shard on the hash ring, so it
+    /// should be stable across restarts. Adding a name that already exists
+    /// replaces its client.
+    pub fn add_shard<S: Into<String>>(&mut self, name: S, client: RiemannClient) {
+        let name = name.into();
+        if let Some(shard) = self.shards.iter_mut().find(|(n, _)| n == &name) {
+            shard.1 = client;
+            return;
+        }
+
+        let idx = self.shards.len();
+        for i in 0..VIRTUAL_NODES {
+            let point = hash_parts(&[name.as_bytes(), &i.to_be_bytes()]);
+            self.ring.insert(point, idx);
+        }
+        self.shards.push((name, client));
+    }
+
+    /// Names of all shards, in the order they were added.
+    pub fn shards(&self) -> impl Iterator<Item = &str> {
+        self.shards.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn shard_index(&self, event: &Event) -> Option<usize> {
+        let values: Vec<&[u8]> = self
+            .fields
+            .iter()
+            .map(|f| f.value(event).unwrap_or("").as_bytes())
+            .collect();
+        let key = hash_parts(&values);
+
+        self.ring
+            .range(key..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, idx)| *idx)
+    }
+
+    /// Name of the shard that `event` is routed to.
+    pub fn shard_for(&self, event: &Event) -> Option<&str> {
+        self.shard_index(event)
+            .map(|idx| self.shards[idx].0.as_str())
+    }
+
+    /// Split `events` by shard and send each part concurrently. One result is
+    /// returned for every shard that received events. Nothing is sent when
+    /// no shard has been added.
+    pub async fn send_events(&self, events: Vec<Event>) -> Vec<ShardResult> {
+        let mut batches: Vec<Vec<Event>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for event in events {
+            if let Some(idx) = self.shard_index(&event) {
+                batches[idx].push(event);
+            }
+        }
+
+        let sends = batches
+            .into_iter()
+            .enumerate()
+            .filter(|(_, batch)| !batch.is_empty())
+            .map(|(idx, batch)| {
+                let (name, client) = &self.shards[idx];
+                async move {
+                    let events = batch.len();
+                    ShardResult {
+                        shard: name.clone(),
+                        events,
+                        result: client.send_events(batch).await,
+                    }
+                }
+            });
+        join_all(sends).await
+    }
+}
+
+/// FNV-1a over all parts, with a separator between parts, followed by a
+/// final avalanche so that similar keys spread over the ring. Stable across
+/// processes and releases, unlike `std::hash`.
+fn hash_parts(parts: &[&[u8]]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for b in part.iter().chain(std::iter::once(&0xff)) {
+            h ^= u64::from(*b);
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
@@ -0,0 +1,435 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use derive_builder::Builder;
+use getset::Getters;
+use prost::Message;
+
+use crate::protos::riemann::Msg;
+
+/// Size of the record header: payload length and CRC32, both big endian u32
+const HEADER_LEN: u64 = 8;
+const SEGMENT_EXT: &str = "seg";
+const CURSOR_FILE: &str = "cursor";
+
+/// What to do when the spool reaches `max_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpoolDropPolicy {
+    /// Delete the oldest segment to make room
+    DropOldest,
+    /// Refuse the new batch
+    DropNewest,
+}
+
+/// Options for `Spool`
+#[derive(Builder, Clone, Debug, Getters)]
+#[builder(setter(into))]
+#[builder(build_fn(skip))]
+#[builder(pattern = "owned")]
+#[get = "pub"]
+pub struct SpoolOptions {
+    /// Upper bound of the spool size on disk
+    max_bytes: u64,
+    /// Size at which a new segment file is started
+    segment_bytes: u64,
+    drop_policy: SpoolDropPolicy,
+}
+
+impl SpoolOptionsBuilder {
+    pub fn build(self) -> SpoolOptions {
+        SpoolOptions {
+            max_bytes: self.max_bytes.unwrap_or(64 * 1024 * 1024),
+            segment_bytes: self.segment_bytes.unwrap_or(4 * 1024 * 1024),
+            drop_policy: self.drop_policy.unwrap_or(SpoolDropPolicy::DropOldest),
+        }
+    }
+}
+
+impl Default for SpoolOptions {
+    fn default() -> SpoolOptions {
+        SpoolOptionsBuilder::default().build()
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    bytes: u64,
+    /// records not consumed yet
+    records: u64,
+}
+
+/// A size-capped, segmented on-disk log of `Msg` batches that could not be
+/// delivered.
+///
+/// Each record is stored with its length and a CRC32 checksum. Records that
+/// fail the check are counted in `corrupted` and the rest of their segment is
+/// skipped. The read position is persisted, so undelivered batches survive a
+/// restart and are delivered at least once.
+///
+/// Attach it to a client with `RiemannClient::with_spool`.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    options: SpoolOptions,
+    /// oldest first, the last one is being appended to
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+    next_id: u64,
+    /// read offset in the first segment
+    cursor: u64,
+    /// length of the record returned by `peek` and not committed yet
+    peeked: Option<u64>,
+    pending: u64,
+    total_bytes: u64,
+    corrupted: u64,
+    dropped: u64,
+}
+
+impl Spool {
+    /// Open the spool in `dir`, creating it if needed, and recover any
+    /// batches left by a previous process.
+    pub fn open<P: AsRef<Path>>(dir: P, options: &SpoolOptions) -> io::Result<Spool> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(SEGMENT_EXT) {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(|s| s.parse::<u64>().ok())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+
+        let (cursor_id, mut cursor) = read_cursor(&dir)?.unwrap_or((0, 0));
+
+        let mut spool = Spool {
+            next_id: ids.last().map(|id| id + 1).unwrap_or(0).max(cursor_id),
+            dir,
+            options: options.clone(),
+            segments: VecDeque::new(),
+            writer: None,
+            cursor: 0,
+            peeked: None,
+            pending: 0,
+            total_bytes: 0,
+            corrupted: 0,
+            dropped: 0,
+        };
+
+        for id in ids {
+            let path = spool.segment_path(id);
+            if id < cursor_id {
+                // fully delivered before the last shutdown
+                fs::remove_file(&path)?;
+                continue;
+            }
+
+            let data = fs::read(&path)?;
+            let (starts, valid_len) = scan_records(&data);
+            if valid_len < data.len() as u64 {
+                spool.corrupted += 1;
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)?
+                    .set_len(valid_len)?;
+            }
+
+            let offset = if spool.segments.is_empty() && id == cursor_id {
+                cursor
+            } else {
+                0
+            };
+            let records = starts.iter().filter(|s| **s >= offset).count() as u64;
+            spool.pending += records;
+            spool.total_bytes += valid_len;
+            spool.segments.push_back(Segment {
+                id,
+                bytes: valid_len,
+                records,
+            });
+        }
+
+        if spool.segments.front().map(|s| s.id) != Some(cursor_id) {
+            cursor = 0;
+        }
+        spool.cursor = cursor;
+        if let Some(tail) = spool.segments.back() {
+            spool.writer = Some(
+                OpenOptions::new()
+                    .append(true)
+                    .open(spool.segment_path(tail.id))?,
+            );
+        }
+        Ok(spool)
+    }
+
+    /// Number of batches waiting to be delivered.
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Bytes used on disk.
+    pub fn size(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Number of times a corrupted record was found. Everything after it in
+    /// the same segment is lost.
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// Number of batches discarded by the drop policy.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Append a batch. Returns `false` when the drop policy refused it.
+    pub fn append(&mut self, msg: &Msg) -> io::Result<bool> {
+        let payload = msg.encode_to_vec();
+        let record_len = HEADER_LEN + payload.len() as u64;
+        let max_bytes = self.options.max_bytes;
+
+        if record_len > max_bytes {
+            self.dropped += 1;
+            return Ok(false);
+        }
+        while self.total_bytes + record_len > max_bytes {
+            match self.options.drop_policy {
+                SpoolDropPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return Ok(false);
+                }
+                SpoolDropPolicy::DropOldest => {
+                    let records = self.segments.front().map(|s| s.records).unwrap_or(0);
+                    self.dropped += records;
+                    self.remove_front()?;
+                }
+            }
+        }
+
+        let rotate = match self.segments.back() {
+            Some(tail) => tail.bytes > 0 && tail.bytes + record_len > self.options.segment_bytes,
+            None => true,
+        };
+        if rotate {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.writer = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.segment_path(id))?,
+            );
+            self.segments.push_back(Segment {
+                id,
+                bytes: 0,
+                records: 0,
+            });
+            if self.segments.len() == 1 {
+                self.cursor = 0;
+                self.write_cursor()?;
+            }
+        }
+
+        let mut buf = Vec::with_capacity(record_len as usize);
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&crc32fast::hash(&payload).to_be_bytes());
+        buf.extend_from_slice(&payload);
+
+        let writer = self.writer.as_mut().expect("spool has a tail segment");
+        writer.write_all(&buf)?;
+        writer.sync_data()?;
+
+        let tail = self.segments.back_mut().expect("spool has a tail segment");
+        tail.bytes += record_len;
+        tail.records += 1;
+        self.total_bytes += record_len;
+        self.pending += 1;
+        Ok(true)
+    }
+
+    /// Read the oldest undelivered batch without consuming it. Call `commit`
+    /// once it is delivered.
+    pub fn peek(&mut self) -> io::Result<Option<Msg>> {
+        self.peeked = None;
+        loop {
+            let (id, bytes) = match self.segments.front() {
+                Some(s) => (s.id, s.bytes),
+                None => return Ok(None),
+            };
+
+            if self.cursor >= bytes {
+                if self.segments.len() == 1 {
+                    return Ok(None);
+                }
+                self.remove_front()?;
+                continue;
+            }
+
+            match read_record(&self.segment_path(id), self.cursor)? {
+                Some((msg, len)) => {
+                    self.peeked = Some(len);
+                    return Ok(Some(msg));
+                }
+                None => {
+                    // the rest of this segment cannot be trusted
+                    self.corrupted += 1;
+                    if self.segments.len() == 1 {
+                        let cursor = self.cursor;
+                        if let Some(writer) = self.writer.as_mut() {
+                            writer.set_len(cursor)?;
+                        }
+                        let tail = self.segments.back_mut().expect("checked above");
+                        self.total_bytes -= tail.bytes - cursor;
+                        self.pending -= tail.records;
+                        tail.bytes = cursor;
+                        tail.records = 0;
+                        return Ok(None);
+                    }
+                    self.remove_front()?;
+                }
+            }
+        }
+    }
+
+    /// Mark the batch returned by the last `peek` as delivered.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let len = match self.peeked.take() {
+            Some(len) => len,
+            None => return Ok(()),
+        };
+
+        self.cursor += len;
+        self.pending -= 1;
+        let front = self.segments.front_mut().expect("peeked from a segment");
+        front.records -= 1;
+
+        if self.cursor >= front.bytes {
+            // reclaim the space of fully delivered segments, including the
+            // tail one
+            self.remove_front()
+        } else {
+            self.write_cursor()
+        }
+    }
+
+    fn remove_front(&mut self) -> io::Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            fs::remove_file(self.segment_path(segment.id))?;
+            self.total_bytes -= segment.bytes;
+            self.pending -= segment.records;
+            if self.segments.is_empty() {
+                self.writer = None;
+            }
+        }
+        self.cursor = 0;
+        self.peeked = None;
+        self.write_cursor()
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", id, SEGMENT_EXT))
+    }
+
+    fn write_cursor(&self) -> io::Result<()> {
+        let id = self.segments.front().map(|s| s.id).unwrap_or(self.next_id);
+        let mut buf = [0u8; 16];
+        buf[..8].copy_from_slice(&id.to_be_bytes());
+        buf[8..].copy_from_slice(&self.cursor.to_be_bytes());
+
+        let tmp = self.dir.join(format!("{}.tmp", CURSOR_FILE));
+        fs::write(&tmp, buf)?;
+        fs::rename(tmp, self.dir.join(CURSOR_FILE))
+    }
+}
+
+fn read_cursor(dir: &Path) -> io::Result<Option<(u64, u64)>> {
+    match fs::read(dir.join(CURSOR_FILE)) {
+        Ok(buf) if buf.len() == 16 => {
+            let mut id = [0u8; 8];
+            let mut offset = [0u8; 8];
+            id.copy_from_slice(&buf[..8]);
+            offset.copy_from_slice(&buf[8..]);
+            Ok(Some((u64::from_be_bytes(id), u64::from_be_bytes(offset))))
+        }
+        // a damaged cursor means replaying from the start of the oldest
+        // segment, which is acceptable for at-least-once delivery
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the start offset of every valid record, and the length of the
+/// valid prefix of `data`.
+fn scan_records(data: &[u8]) -> (Vec<u64>, u64) {
+    let mut starts = Vec::new();
+    let mut offset = 0usize;
+
+    while let Some((_, len)) = parse_record(&data[offset..]) {
+        starts.push(offset as u64);
+        offset += len;
+    }
+    (starts, offset as u64)
+}
+
+/// Parse one record at the beginning of `data`, returning its payload and
+/// total length, or `None` if it is truncated or fails the checksum.
+fn parse_record(data: &[u8]) -> Option<(&[u8], usize)> {
+    if data.len() < HEADER_LEN as usize {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    let mut crc = [0u8; 4];
+    len.copy_from_slice(&data[..4]);
+    crc.copy_from_slice(&data[4..8]);
+    let len = u32::from_be_bytes(len) as usize;
+    let crc = u32::from_be_bytes(crc);
+
+    let payload = data.get(HEADER_LEN as usize..HEADER_LEN as usize + len)?;
+    if crc32fast::hash(payload) != crc {
+        return None;
+    }
+    Some((payload, HEADER_LEN as usize + len))
+}
+
+fn read_record(path: &Path, offset: u64) -> io::Result<Option<(Msg, u64)>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; HEADER_LEN as usize];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[..4]);
+    let len = u32::from_be_bytes(len) as usize;
+
+    let mut record = header.to_vec();
+    record.resize(HEADER_LEN as usize + len, 0);
+    match file.read_exact(&mut record[HEADER_LEN as usize..]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    Ok(parse_record(&record)
+        .and_then(|(payload, len)| Msg::decode(payload).ok().map(|msg| (msg, len as u64))))
+}
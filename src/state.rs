@@ -1,54 +1,235 @@
 use std::future::Future;
-use std::io;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Sleep};
 
 use crate::error::RiemannClientError;
 use crate::options::RiemannClientOptions;
+use crate::retry::backoff;
 use crate::transport::Transport;
 
 pub(crate) enum ClientState {
     Connected(Arc<Transport>),
-    Connecting(BoxFuture<'static, Result<Transport, io::Error>>),
+    Connecting(BoxFuture<'static, Result<(usize, Transport), RiemannClientError>>),
+    /// waiting before the next connection attempt
+    Backoff(Pin<Box<Sleep>>),
     Disconnected,
+    /// the client is shut down, no more connections are made
+    Closed,
 }
 
 pub(crate) struct Inner {
     pub(crate) options: RiemannClientOptions,
     pub(crate) state: ClientState,
+    /// index of the endpoint this connection is made to
+    pub(crate) endpoint: usize,
+    /// index of the endpoint any connection of the pool was last made to,
+    /// tried first on next connect
+    pub(crate) active_endpoint: Arc<AtomicUsize>,
+    /// when to start probing the primary while on a fallback endpoint
+    pub(crate) failback_at: Option<Instant>,
+    pub(crate) failback_probe: Option<JoinHandle<Result<Transport, RiemannClientError>>>,
+    /// consecutive failed connection attempts
+    pub(crate) failures: u32,
+    /// set while the circuit breaker is open or half-open
+    pub(crate) circuit_open_until: Option<Instant>,
+    /// notified whenever a connection is made
+    pub(crate) connected: Arc<Notify>,
+}
+
+impl Inner {
+    pub(crate) fn new(
+        options: &RiemannClientOptions,
+        active_endpoint: Arc<AtomicUsize>,
+        connected: Arc<Notify>,
+    ) -> Inner {
+        Inner {
+            options: options.clone(),
+            state: ClientState::Disconnected,
+            endpoint: 0,
+            active_endpoint,
+            failback_at: None,
+            failback_probe: None,
+            failures: 0,
+            circuit_open_until: None,
+            connected,
+        }
+    }
+
+    /// Stop making connections, and hand back the current one if any.
+    pub(crate) fn close(&mut self) -> Option<Arc<Transport>> {
+        if let Some(probe) = self.failback_probe.take() {
+            probe.abort();
+        }
+        self.failback_at = None;
+        match std::mem::replace(&mut self.state, ClientState::Closed) {
+            ClientState::Connected(conn) => Some(conn),
+            _ => None,
+        }
+    }
+
+    fn start_connecting(&mut self, cx: &mut Context) {
+        let start = self.active_endpoint.load(Ordering::Relaxed);
+        let f = connect_with_failover(self.options.clone(), start).boxed();
+        self.state = ClientState::Connecting(f);
+        cx.waker().wake_by_ref();
+    }
+
+    fn on_connect_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        let threshold = *self.options.circuit_breaker_threshold();
+        // a failed attempt while half-open reopens the circuit right away
+        if self.circuit_open_until.is_some() || (threshold > 0 && self.failures >= threshold) {
+            let cooldown = Duration::from_millis(*self.options.circuit_breaker_cooldown_ms());
+            self.circuit_open_until = Some(Instant::now() + cooldown);
+        }
+        self.state = ClientState::Disconnected;
+    }
+
+    fn failback_interval(&self) -> Option<Duration> {
+        match *self.options.failback_interval_ms() {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// While connected to a fallback endpoint, periodically dial the primary
+    /// in background and switch over once it answers.
+    fn poll_failback(&mut self, cx: &mut Context) {
+        if let Some(ref mut probe) = self.failback_probe {
+            match probe.poll_unpin(cx) {
+                Poll::Ready(Ok(Ok(conn))) => {
+                    self.state = ClientState::Connected(Arc::new(conn));
+                    self.endpoint = 0;
+                    self.active_endpoint.store(0, Ordering::Relaxed);
+                    self.connected.notify_one();
+                    self.failback_at = None;
+                    self.failback_probe = None;
+                }
+                Poll::Ready(_) => {
+                    self.failback_at = self.failback_interval().map(|i| Instant::now() + i);
+                    self.failback_probe = None;
+                }
+                Poll::Pending => {}
+            }
+        } else if self
+            .failback_at
+            .map(|t| t <= Instant::now())
+            .unwrap_or(false)
+        {
+            let primary = self.options.endpoints()[0].clone();
+            let probe = tokio::spawn(Transport::connect(self.options.clone(), primary));
+            self.failback_probe = Some(probe);
+        }
+    }
+}
+
+/// Try each endpoint in turn, starting from `start`, and return the index
+/// of the first one that accepts the connection.
+async fn connect_with_failover(
+    options: RiemannClientOptions,
+    start: usize,
+) -> Result<(usize, Transport), RiemannClientError> {
+    let endpoints = options.endpoints();
+    let mut last_error = None;
+
+    for i in 0..endpoints.len() {
+        let idx = (start + i) % endpoints.len();
+        match Transport::connect(options.clone(), endpoints[idx].clone()).await {
+            Ok(conn) => return Ok((idx, conn)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| RiemannClientError::InvalidOptions("No endpoint configured".to_owned())))
 }
 
 impl Future for Inner {
     type Output = Result<Arc<Transport>, RiemannClientError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let ClientState::Connected(ref conn) = self.state {
+            // calls still using the old connection finish on it, it closes
+            // once they are done
+            if conn.is_stale(&self.options) {
+                self.state = ClientState::Disconnected;
+            }
+        }
+        if let ClientState::Connected(_) = self.state {
+            if self.endpoint != 0 {
+                self.poll_failback(cx);
+            }
+        }
+
         match &mut self.state {
             ClientState::Connected(conn) => Poll::Ready(Ok(conn.clone())),
+            ClientState::Closed => Poll::Ready(Err(RiemannClientError::Closed)),
             ClientState::Connecting(ref mut f) => match f.poll_unpin(cx) {
-                Poll::Ready(Ok(conn)) => {
-                    // connected
+                Poll::Ready(Ok((idx, conn))) => {
+                    // connected, close the circuit
+                    self.failures = 0;
+                    self.circuit_open_until = None;
                     let connection = Arc::new(conn);
                     self.state = ClientState::Connected(connection.clone());
+                    self.endpoint = idx;
+                    self.active_endpoint.store(idx, Ordering::Relaxed);
+                    self.connected.notify_one();
+                    self.failback_at = if idx != 0 {
+                        self.failback_interval().map(|i| Instant::now() + i)
+                    } else {
+                        None
+                    };
                     Poll::Ready(Ok(connection))
                 }
                 Poll::Ready(Err(e)) => {
                     // failed to connect, reset to disconnected
-                    self.state = ClientState::Disconnected;
-                    Poll::Ready(Err(RiemannClientError::from(e)))
+                    self.on_connect_failure();
+                    Poll::Ready(Err(e))
                 }
                 Poll::Pending => {
                     // still connecting
                     Poll::Pending
                 }
             },
+            ClientState::Backoff(ref mut delay) => match delay.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    self.start_connecting(cx);
+                    Poll::Pending
+                }
+                Poll::Pending => Poll::Pending,
+            },
             ClientState::Disconnected => {
-                let f = Transport::connect(self.options.clone()).boxed();
-                self.state = ClientState::Connecting(f);
-                cx.waker().clone().wake();
+                if let Some(probe) = self.failback_probe.take() {
+                    probe.abort();
+                }
+
+                if let Some(open_until) = self.circuit_open_until {
+                    let now = Instant::now();
+                    if now < open_until {
+                        return Poll::Ready(Err(RiemannClientError::CircuitOpen(open_until - now)));
+                    }
+                    // half-open, let one attempt through without delay
+                    self.start_connecting(cx);
+                } else if self.failures > 0 {
+                    let delay = backoff(
+                        *self.options.reconnect_backoff_ms(),
+                        *self.options.reconnect_backoff_max_ms(),
+                        self.failures,
+                    );
+                    self.state = ClientState::Backoff(Box::pin(sleep(delay)));
+                    cx.waker().wake_by_ref();
+                } else {
+                    self.start_connecting(cx);
+                }
                 Poll::Pending
             }
         }
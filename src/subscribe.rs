@@ -0,0 +1,252 @@
+use std::fmt::Write;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+#[cfg(feature = "tls")]
+use tokio_rustls::client::TlsStream;
+use tokio_tungstenite::tungstenite::error::{Error as WsError, ProtocolError};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{client_async, WebSocketStream};
+
+use crate::error::{Operation, RiemannClientError};
+use crate::json;
+use crate::options::{Endpoint, RiemannClientOptions};
+use crate::protos::riemann::Event;
+use crate::retry::backoff;
+#[cfg(feature = "tls")]
+use crate::tls::{current_tls_config, setup_tls_client};
+
+/// A plain or TLS socket
+trait Socket: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Socket for S {}
+
+type WsStream = WebSocketStream<Box<dyn Socket>>;
+
+/// A live stream of the events matching a query, from riemann's websocket
+/// server.
+///
+/// The subscription reconnects with backoff when the connection drops, and
+/// yields an `Err` for every failed attempt. Events indexed while it was
+/// disconnected are not replayed. Dropping the stream cancels the
+/// subscription.
+///
+/// With `use_tls`, the subscription connects over `wss://` with the TLS
+/// config of the client.
+#[derive(Debug)]
+pub struct Subscription {
+    events: Receiver<Result<Event, RiemannClientError>>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    pub(crate) fn new(options: &RiemannClientOptions, endpoint: &Endpoint, query: &str) -> Self {
+        let endpoint = Endpoint::new(endpoint.host().clone(), *options.ws_port());
+        let url = subscribe_url(&endpoint, use_tls(options), query);
+        let (tx, rx) = mpsc::channel(1024);
+        let task = tokio::spawn(subscribe_loop(url, endpoint, options.clone(), tx));
+
+        Subscription { events: rx, task }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<Event, RiemannClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn subscribe_loop(
+    url: String,
+    endpoint: Endpoint,
+    options: RiemannClientOptions,
+    tx: Sender<Result<Event, RiemannClientError>>,
+) {
+    if let Some(reason) = options.invalid() {
+        let _ = tx
+            .send(Err(RiemannClientError::InvalidOptions(reason.clone())))
+            .await;
+        return;
+    }
+
+    let mut failures = 0;
+    loop {
+        if failures > 0 {
+            sleep(backoff(
+                *options.reconnect_backoff_ms(),
+                *options.reconnect_backoff_max_ms(),
+                failures,
+            ))
+            .await;
+        }
+
+        let connect_timeout = Duration::from_millis(*options.connect_timeout_ms());
+        let ws = match timeout(connect_timeout, connect(&url, &endpoint, &options)).await {
+            Ok(ws) => ws,
+            Err(_) => Err(RiemannClientError::ConnectTimeout {
+                endpoint: url.clone(),
+                timeout: connect_timeout,
+            }),
+        };
+
+        match ws {
+            Ok(ws) => {
+                if !forward_events(ws, &url, &tx).await {
+                    return;
+                }
+                // the server went away, subscribe again after the shortest
+                // backoff
+                failures = 1;
+            }
+            Err(e) => {
+                failures = failures.saturating_add(1);
+                if tx.send(Err(e)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Hand events from `ws` to the subscriber until the connection ends.
+/// Returns false once the subscriber is gone.
+async fn forward_events(
+    mut ws: WsStream,
+    url: &str,
+    tx: &Sender<Result<Event, RiemannClientError>>,
+) -> bool {
+    while let Some(msg) = ws.next().await {
+        let event = match msg {
+            Ok(Message::Text(text)) => {
+                json::from_str(text.as_str()).map_err(|e| RiemannClientError::Protocol {
+                    endpoint: url.to_owned(),
+                    reason: e.to_string(),
+                })
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                if tx.send(Err(ws_error(e, url))).await.is_err() {
+                    return false;
+                }
+                break;
+            }
+        };
+        if tx.send(event).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// A connection that dropped is `ConnectionClosed`, anything else the
+/// server sent that is not websocket is `Protocol`.
+fn ws_error(e: WsError, url: &str) -> RiemannClientError {
+    match e {
+        WsError::ConnectionClosed
+        | WsError::AlreadyClosed
+        | WsError::Io(_)
+        | WsError::Protocol(ProtocolError::ResetWithoutClosingHandshake) => {
+            RiemannClientError::ConnectionClosed {
+                endpoint: url.to_owned(),
+                operation: Operation::Subscribe,
+            }
+        }
+        e => RiemannClientError::Protocol {
+            endpoint: url.to_owned(),
+            reason: e.to_string(),
+        },
+    }
+}
+
+/// Open a websocket to `endpoint`, over TLS when the client uses TLS.
+async fn connect(
+    url: &str,
+    endpoint: &Endpoint,
+    options: &RiemannClientOptions,
+) -> Result<WsStream, RiemannClientError> {
+    let connect_error = |source| RiemannClientError::Connect {
+        endpoint: url.to_owned(),
+        source,
+    };
+    let socket = TcpStream::connect((endpoint.host().as_str(), *endpoint.port()))
+        .await
+        .map_err(connect_error)?;
+
+    #[cfg(feature = "tls")]
+    let socket: Box<dyn Socket> = if *options.use_tls() {
+        Box::new(tls_handshake(url, socket, endpoint, options).await?)
+    } else {
+        Box::new(socket)
+    };
+    #[cfg(not(feature = "tls"))]
+    let socket: Box<dyn Socket> = {
+        let _ = options;
+        Box::new(socket)
+    };
+
+    let (ws, _) = client_async(url, socket)
+        .await
+        .map_err(|e| connect_error(io::Error::other(e)))?;
+    Ok(ws)
+}
+
+#[cfg(feature = "tls")]
+async fn tls_handshake(
+    url: &str,
+    socket: TcpStream,
+    endpoint: &Endpoint,
+    options: &RiemannClientOptions,
+) -> Result<TlsStream<TcpStream>, RiemannClientError> {
+    let (tls_config, _) = current_tls_config(options)?;
+    let tls_error = |source| RiemannClientError::tls(url.to_owned(), source);
+    let handshake =
+        setup_tls_client(socket, tls_config, options, endpoint.host()).map_err(tls_error)?;
+    handshake.await.map_err(tls_error)
+}
+
+#[cfg(feature = "tls")]
+fn use_tls(options: &RiemannClientOptions) -> bool {
+    *options.use_tls()
+}
+
+#[cfg(not(feature = "tls"))]
+fn use_tls(_: &RiemannClientOptions) -> bool {
+    false
+}
+
+fn subscribe_url(endpoint: &Endpoint, tls: bool, query: &str) -> String {
+    let scheme = if tls { "wss" } else { "ws" };
+    let host = endpoint.host();
+    let mut url = if host.contains(':') {
+        format!("{}://[{}]", scheme, host)
+    } else {
+        format!("{}://{}", scheme, host)
+    };
+    write!(url, ":{}/index?subscribe=true&query=", endpoint.port()).unwrap();
+    for b in query.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                url.push(b as char)
+            }
+            _ => write!(url, "%{:02X}", b).unwrap(),
+        }
+    }
+    url
+}
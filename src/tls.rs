@@ -1,24 +1,378 @@
 use std::convert::TryFrom;
+use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, SystemTime};
 
-use rustls_pki_types::ServerName;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use sha2::{Digest, Sha256};
 use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+};
+use tokio_rustls::rustls::server::ParsedCertificate;
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore,
+    SignatureScheme,
+};
 use tokio_rustls::{Connect, TlsConnector};
 
+use crate::error::RiemannClientError;
 use crate::options::RiemannClientOptions;
 
+/// A SHA-256 fingerprint the riemann server certificate must match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsPin {
+    /// Fingerprint of the whole DER certificate
+    Certificate([u8; 32]),
+    /// Fingerprint of the DER SubjectPublicKeyInfo, which still matches after
+    /// the certificate is renewed with the same key
+    PublicKey([u8; 32]),
+}
+
+impl TlsPin {
+    /// Pin a certificate by its SHA-256 fingerprint in hex, as printed by
+    /// `openssl x509 -noout -fingerprint -sha256`
+    pub fn certificate_sha256(hex: &str) -> Result<TlsPin, RiemannClientError> {
+        parse_fingerprint(hex).map(TlsPin::Certificate)
+    }
+
+    /// Pin a public key by the SHA-256 fingerprint of its SubjectPublicKeyInfo
+    /// in hex
+    pub fn public_key_sha256(hex: &str) -> Result<TlsPin, RiemannClientError> {
+        parse_fingerprint(hex).map(TlsPin::PublicKey)
+    }
+
+    fn matches(&self, cert: &CertificateDer, spki: &[u8]) -> bool {
+        match self {
+            TlsPin::Certificate(fp) => Sha256::digest(cert.as_ref()).as_slice() == fp,
+            TlsPin::PublicKey(fp) => Sha256::digest(spki).as_slice() == fp,
+        }
+    }
+}
+
+fn parse_fingerprint(hex: &str) -> Result<[u8; 32], RiemannClientError> {
+    let invalid =
+        || RiemannClientError::InvalidOptions(format!("invalid SHA-256 fingerprint {}", hex));
+
+    // colons between bytes are optional
+    let digits: Vec<u8> = hex.bytes().filter(|b| *b != b':').collect();
+    if digits.len() != 64 {
+        return Err(invalid());
+    }
+    let mut fp = [0u8; 32];
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        fp[i] = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(fp)
+}
+
+/// Trusts a server certificate that matches one of the pins, instead of
+/// checking it against CA certificates and the server name.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pins: Vec<TlsPin>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        let spki = ParsedCertificate::try_from(end_entity)?.subject_public_key_info();
+        if self
+            .pins
+            .iter()
+            .any(|pin| pin.matches(end_entity, spki.as_ref()))
+        {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Build a `ClientConfig` from `settings`. The server certificate is checked
+/// against the pins if there are any, else against the CA certificates in
+/// `ca_file`, or the webpki roots when it is not set. The client
+/// authenticates with the certificate chain in `cert_file` and the key in
+/// `key_file` when they are set. All files are PEM.
+pub(crate) fn tls_config(settings: &TlsSettings) -> Result<Arc<ClientConfig>, RiemannClientError> {
+    let builder = ClientConfig::builder();
+    let builder = match (settings.ca_file.as_deref(), settings.pins.is_empty()) {
+        (Some(_), false) => {
+            return Err(RiemannClientError::InvalidOptions(
+                "tls_ca_file cannot be combined with pins".to_owned(),
+            ))
+        }
+        (None, false) => {
+            let verifier = PinnedCertVerifier {
+                pins: settings.pins.clone(),
+                algorithms: builder.crypto_provider().signature_verification_algorithms,
+            };
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+        }
+        (Some(path), true) => {
+            let mut store = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                store
+                    .add(cert)
+                    .map_err(|e| invalid_file(path, e.to_string()))?;
+            }
+            builder.with_root_certificates(store)
+        }
+        (None, true) => builder.with_root_certificates(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        }),
+    };
+
+    let (cert_file, key_file) = (settings.cert_file.as_deref(), settings.key_file.as_deref());
+
+    let tls_config = match (cert_file, key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let certs = read_certs(cert_file)?;
+            let key = PrivateKeyDer::from_pem_slice(&read_file(key_file)?)
+                .map_err(|e| invalid_file(key_file, format!("no usable private key ({})", e)))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| invalid_file(key_file, e.to_string()))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(RiemannClientError::InvalidOptions(
+                "tls_cert_file and tls_key_file must be set together".to_owned(),
+            ))
+        }
+    };
+    Ok(Arc::new(tls_config))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, RiemannClientError> {
+    let certs = CertificateDer::pem_slice_iter(&read_file(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid_file(path, e.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid_file(path, "no certificate found"));
+    }
+    Ok(certs)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, RiemannClientError> {
+    fs::read(path).map_err(|e| invalid_file(path, e.to_string()))
+}
+
+fn invalid_file<S: AsRef<str>>(path: &Path, reason: S) -> RiemannClientError {
+    RiemannClientError::InvalidOptions(format!("{}: {}", path.display(), reason.as_ref()))
+}
+
+/// The PEM files and pins a TLS config is built from
+#[derive(Clone, Debug)]
+pub(crate) struct TlsSettings {
+    pub(crate) ca_file: Option<PathBuf>,
+    pub(crate) cert_file: Option<PathBuf>,
+    pub(crate) key_file: Option<PathBuf>,
+    pub(crate) pins: Vec<TlsPin>,
+}
+
+impl TlsSettings {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ca_file.is_none()
+            && self.cert_file.is_none()
+            && self.key_file.is_none()
+            && self.pins.is_empty()
+    }
+
+    pub(crate) fn load(&self) -> Result<Arc<ClientConfig>, RiemannClientError> {
+        tls_config(self)
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [&self.ca_file, &self.cert_file, &self.key_file]
+            .iter()
+            .map(|f| {
+                f.as_ref()
+                    .and_then(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            })
+            .collect()
+    }
+}
+
+/// The TLS config new connections are made with. It can be replaced while
+/// the client runs, and counts replacements so that connections made with an
+/// older config can be recycled.
+pub(crate) struct TlsSource {
+    // `None` until the PEM files are first loaded
+    current: RwLock<(Option<Arc<ClientConfig>>, u64)>,
+    settings: Option<TlsSettings>,
+    // modification times of the PEM files when they were last loaded
+    loaded: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsSource {
+    pub(crate) fn new(config: Arc<ClientConfig>) -> TlsSource {
+        TlsSource {
+            current: RwLock::new((Some(config), 0)),
+            settings: None,
+            loaded: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A source loading the PEM files of `settings` on first use.
+    pub(crate) fn from_settings(settings: TlsSettings) -> TlsSource {
+        TlsSource {
+            current: RwLock::new((None, 0)),
+            settings: Some(settings),
+            loaded: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A source starting from the current config of this one, that is
+    /// replaced and reloaded independently of it.
+    pub(crate) fn fork(&self) -> TlsSource {
+        TlsSource {
+            current: RwLock::new((self.current.read().unwrap().0.clone(), 0)),
+            settings: self.settings.clone(),
+            loaded: Mutex::new(self.loaded.lock().unwrap().clone()),
+        }
+    }
+
+    /// The config to connect with, and its generation. The PEM files are
+    /// loaded if they were not yet.
+    pub(crate) fn current(&self) -> Result<(Arc<ClientConfig>, u64), RiemannClientError> {
+        if let (Some(ref config), generation) = *self.current.read().unwrap() {
+            return Ok((config.clone(), generation));
+        }
+
+        let mut current = self.current.write().unwrap();
+        if current.0.is_none() {
+            let settings = self
+                .settings
+                .as_ref()
+                .expect("a source without config has settings");
+            let modified = settings.modified();
+            current.0 = Some(settings.load()?);
+            *self.loaded.lock().unwrap() = modified;
+        }
+        Ok((current.0.clone().unwrap(), current.1))
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.current.read().unwrap().1
+    }
+
+    pub(crate) fn replace(&self, config: Arc<ClientConfig>) {
+        let mut current = self.current.write().unwrap();
+        *current = (Some(config), current.1 + 1);
+    }
+
+    /// Load the PEM files again. The current config stays in use when they
+    /// cannot be loaded.
+    pub(crate) fn reload(&self) -> Result<(), RiemannClientError> {
+        let settings = self.settings.as_ref().ok_or_else(|| {
+            RiemannClientError::InvalidOptions("TLS is not configured from PEM files".to_owned())
+        })?;
+
+        // taken before reading, so a change made while reading is picked up
+        // by the next check
+        let modified = settings.modified();
+        self.replace(settings.load()?);
+        *self.loaded.lock().unwrap() = modified;
+        Ok(())
+    }
+
+    /// Load the PEM files again if any of them changed since they were last
+    /// loaded. Returns whether the config was replaced.
+    pub(crate) fn reload_if_modified(&self) -> Result<bool, RiemannClientError> {
+        match self.settings {
+            Some(ref settings) if settings.modified() != *self.loaded.lock().unwrap() => {
+                self.reload().map(|_| true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Check the PEM files of `source` every `interval` and load them again when
+/// they changed, until the client is dropped. Files that fail to load, for
+/// instance while they are being rewritten, are tried again on the next
+/// check.
+pub(crate) async fn watch_pem_files(source: Weak<TlsSource>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick is immediate, and the files were just loaded
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match source.upgrade() {
+            Some(source) => {
+                let _ = source.reload_if_modified();
+            }
+            None => return,
+        }
+    }
+}
+
+/// The current TLS config of `options`, and its generation.
+pub(crate) fn current_tls_config(
+    options: &RiemannClientOptions,
+) -> Result<(Arc<ClientConfig>, u64), RiemannClientError> {
+    options
+        .tls_source()
+        .as_ref()
+        .ok_or_else(|| {
+            RiemannClientError::InvalidOptions("use_tls is set without tls_config".to_owned())
+        })?
+        .current()
+}
+
+/// Start a TLS handshake with `tls_config`, checking the server certificate
+/// against `host` unless `options` name another server.
 pub(crate) fn setup_tls_client(
     socket: TcpStream,
+    tls_config: Arc<ClientConfig>,
     options: &RiemannClientOptions,
+    host: &str,
 ) -> Result<Connect<TcpStream>, io::Error> {
-    let tls_config = if let Some(tls_config) = options.tls_config() {
-        tls_config.clone()
-    } else {
-        unreachable!("tls_config cannot be None when use_tls is true");
-    };
     let connector = TlsConnector::from(tls_config);
 
-    let dns_name = ServerName::try_from(options.host().as_str())
+    let host = options.tls_server_name().as_deref().unwrap_or(host);
+    let dns_name = ServerName::try_from(host)
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid DnsName"))?
         .to_owned();
     Ok(connector.connect(dns_name, socket))
@@ -1,205 +1,502 @@
+use std::collections::VecDeque;
 use std::io;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use futures::lock::Mutex;
-use futures::stream::{SplitSink, StreamExt};
-use futures::{SinkExt, TryFutureExt};
+use futures::stream::{SplitSink, SplitStream, StreamExt};
+use futures::{Future, SinkExt};
 use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::net::{TcpStream, UdpSocket};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::{self, Sender};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 use tokio_util::codec::Framed;
 
-#[cfg(feature = "tls")]
-use tokio_rustls::client::TlsStream;
-
 use crate::codec::{encode_for_udp, MsgCodec};
-use crate::options::RiemannClientOptions;
+use crate::error::{Operation, RiemannClientError};
+use crate::options::{Endpoint, RiemannClientOptions};
 use crate::protos::riemann::{Event, Msg, Query};
 #[cfg(feature = "tls")]
-use crate::tls::setup_tls_client;
+use crate::tls::{current_tls_config, setup_tls_client};
 
 #[derive(Debug)]
 pub(crate) enum Transport {
-    Plain(TcpTransportInner<TcpStream>),
+    Plain(TcpTransportInner),
+    /// A TLS connection, with the generation of the TLS config it was made
+    /// with
     #[cfg(feature = "tls")]
-    Tls(TcpTransportInner<TlsStream<TcpStream>>),
+    Tls(TcpTransportInner, u64),
+    #[cfg(unix)]
+    Unix(TcpTransportInner),
     Udp(UdpTransportInner),
 }
 
+/// A request waiting for its response. Riemann answers in request order, so
+/// slots are matched to responses first in, first out.
 #[derive(Debug)]
-pub(crate) struct TcpTransportInner<S>
-where
-    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-{
-    sender_queue: UnboundedSender<Sender<Msg>>,
-    socket_sender: Mutex<SplitSink<Framed<S, MsgCodec>, Msg>>,
+struct Slot {
+    tx: Sender<Result<Msg, RiemannClientError>>,
+    // keeps a place in the in-flight window until the response arrives, even
+    // if the caller has given up waiting
+    _permit: OwnedSemaphorePermit,
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    queue: VecDeque<Slot>,
+    closed: bool,
 }
 
-impl<S: AsyncRead + AsyncWrite + Unpin + Send> TcpTransportInner<S> {
-    fn setup_conn(socket: S) -> TcpTransportInner<S> {
+/// A pipelined connection over a stream socket.
+///
+/// Requests are written by a writer task in the order they are submitted,
+/// and responses are handed out by a reader task, so many requests can be in
+/// flight at once, up to the window size. A caller that times out leaves its
+/// slot in place; its response is discarded when it arrives, which keeps
+/// later responses matched to the right callers.
+#[derive(Debug)]
+pub(crate) struct TcpTransportInner {
+    /// `host:port`, or the path of a unix socket
+    endpoint: String,
+    requests: UnboundedSender<(Msg, Slot)>,
+    window: Arc<Semaphore>,
+    writer: JoinHandle<()>,
+    reader: JoinHandle<()>,
+}
+
+impl TcpTransportInner {
+    fn setup_conn<S>(socket: S, max_in_flight: usize, endpoint: String) -> TcpTransportInner
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let framed = Framed::new(socket, MsgCodec::default());
-        let (conn_sender, mut conn_receiver) = framed.split();
-        let (cb_queue_tx, mut cb_queue_rx) = mpsc::unbounded_channel::<Sender<Msg>>();
-
-        let receiver_loop = async move {
-            loop {
-                let frame = conn_receiver.next().await;
-                let cb = cb_queue_rx.recv().await;
-                if let (Some(Ok(frame)), Some(cb)) = (frame, cb) {
-                    let r = cb.send(frame);
-                    if r.is_err() {
-                        // eprintln!("failed to deliver msg to callback {:?}", e);
-                        break;
-                    }
-                } else {
-                    // eprintln!("failed to deliver msg to callback.");
-                    break;
-                }
-            }
-        };
-        tokio::spawn(receiver_loop);
+        let (conn_sender, conn_receiver) = framed.split();
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let slots = Arc::new(Mutex::new(Slots::default()));
+        // dropped by the writer when it stops, to stop the reader as well
+        let (writer_done_tx, writer_done_rx) = oneshot::channel();
+
+        let writer = tokio::spawn(write_loop(
+            conn_sender,
+            requests_rx,
+            slots.clone(),
+            writer_done_tx,
+        ));
+        let reader = tokio::spawn(read_loop(
+            conn_receiver,
+            slots,
+            writer_done_rx,
+            endpoint.clone(),
+        ));
 
         TcpTransportInner {
-            sender_queue: cb_queue_tx,
-            socket_sender: Mutex::new(conn_sender),
+            endpoint,
+            requests: requests_tx,
+            window: Arc::new(Semaphore::new(max_in_flight.max(1))),
+            writer,
+            reader,
         }
     }
 
-    async fn send_for_response(&self, msg: Msg, socket_timeout: u64) -> Result<Msg, io::Error> {
-        let (tx, rx) = oneshot::channel::<Msg>();
+    /// Shut down the write side of the socket once everything submitted is
+    /// written, and wait for both tasks to stop.
+    async fn close(self) {
+        drop(self.requests);
+        let _ = self.writer.await;
+        let _ = self.reader.await;
+    }
+
+    async fn send_for_response(
+        &self,
+        msg: Msg,
+        operation: Operation,
+        socket_timeout: u64,
+    ) -> Result<Msg, RiemannClientError> {
+        let closed = || RiemannClientError::ConnectionClosed {
+            endpoint: self.endpoint.clone(),
+            operation,
+        };
+        let request = async {
+            let permit = self
+                .window
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|_| closed())?;
+            let (tx, rx) = oneshot::channel();
+
+            self.requests
+                .send((
+                    msg,
+                    Slot {
+                        tx,
+                        _permit: permit,
+                    },
+                ))
+                .map_err(|_| closed())?;
+
+            rx.await.map_err(|_| closed())?
+        };
+
+        let socket_timeout = Duration::from_millis(socket_timeout);
+        timeout(socket_timeout, request)
+            .await
+            .map_err(|_| RiemannClientError::ResponseTimeout {
+                endpoint: self.endpoint.clone(),
+                operation,
+                timeout: socket_timeout,
+            })?
+    }
+}
 
-        let mut sender = self.socket_sender.lock().await;
-        self.sender_queue
-            .send(tx)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+async fn write_loop<S>(
+    mut sink: SplitSink<Framed<S, MsgCodec>, Msg>,
+    mut requests: UnboundedReceiver<(Msg, Slot)>,
+    slots: Arc<Mutex<Slots>>,
+    _done: oneshot::Sender<()>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    while let Some(request) = requests.recv().await {
+        // write everything already submitted, then flush once
+        let mut next = Some(request);
+        while let Some((msg, slot)) = next {
+            {
+                let mut slots = slots.lock().unwrap();
+                if slots.closed {
+                    return;
+                }
+                slots.queue.push_back(slot);
+            }
+            if sink.feed(msg).await.is_err() {
+                return;
+            }
+            next = requests.try_recv().ok();
+        }
+        if sink.flush().await.is_err() {
+            return;
+        }
+    }
 
-        sender
-            .send(msg)
-            .map_err(|e| io::Error::new(io::ErrorKind::UnexpectedEof, e))
-            .await?;
+    // the transport is dropped, shut down the write side cleanly
+    let _ = sink.close().await;
+}
 
-        timeout(Duration::from_millis(socket_timeout), rx)
-            .await?
-            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))
+async fn read_loop<S>(
+    mut stream: SplitStream<Framed<S, MsgCodec>>,
+    slots: Arc<Mutex<Slots>>,
+    mut writer_done: oneshot::Receiver<()>,
+    endpoint: String,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    loop {
+        tokio::select! {
+            frame = stream.next() => match frame {
+                Some(Ok(msg)) => {
+                    let slot = slots.lock().unwrap().queue.pop_front();
+                    match slot {
+                        // the receiver is gone if the caller timed out
+                        Some(slot) => {
+                            let _ = slot.tx.send(Ok(msg));
+                        }
+                        // a response nobody asked for, the stream is out of sync
+                        None => break,
+                    }
+                }
+                // a frame that cannot be decoded fails everyone waiting,
+                // the connection cannot be trusted anymore
+                Some(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                    let mut slots = slots.lock().unwrap();
+                    for slot in slots.queue.drain(..) {
+                        let _ = slot.tx.send(Err(RiemannClientError::Protocol {
+                            endpoint: endpoint.clone(),
+                            reason: e.to_string(),
+                        }));
+                    }
+                    break;
+                }
+                _ => break,
+            },
+            _ = &mut writer_done => break,
+        }
     }
+
+    // fail everyone still waiting
+    let mut slots = slots.lock().unwrap();
+    slots.closed = true;
+    slots.queue.clear();
 }
 
 #[derive(Debug)]
 pub(crate) struct UdpTransportInner {
+    endpoint: String,
     socket: UdpSocket,
+    max_datagram_size: usize,
 }
 
 impl UdpTransportInner {
-    async fn new(options: &RiemannClientOptions) -> Result<UdpTransportInner, io::Error> {
+    async fn new(
+        endpoint: &Endpoint,
+        max_datagram_size: usize,
+    ) -> Result<UdpTransportInner, RiemannClientError> {
+        let endpoint = endpoint.to_string();
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect(options.to_socket_addr_string()).await?;
+        socket
+            .connect(&endpoint)
+            .await
+            .map_err(|source| RiemannClientError::Connect {
+                endpoint: endpoint.clone(),
+                source,
+            })?;
 
-        Ok(UdpTransportInner { socket })
+        Ok(UdpTransportInner {
+            endpoint,
+            socket,
+            max_datagram_size,
+        })
     }
 
-    async fn send_without_response(&self, msg: Msg) -> Result<(), io::Error> {
-        let buf = encode_for_udp(&msg)?;
-
-        self.socket.send(buf.as_ref()).await.map(|_| ())
+    /// Send `events` in as few datagrams as fit. Nothing is sent if any
+    /// single event is too large for a datagram.
+    async fn send_without_response(&self, events: Vec<Event>) -> Result<(), RiemannClientError> {
+        for buf in encode_for_udp(events, self.max_datagram_size)? {
+            self.socket
+                .send(buf.as_ref())
+                .await
+                .map_err(|source| RiemannClientError::Connect {
+                    endpoint: self.endpoint.clone(),
+                    source,
+                })?;
+        }
+        Ok(())
     }
 }
 
 impl Transport {
-    pub(crate) async fn connect(options: RiemannClientOptions) -> Result<Transport, io::Error> {
+    pub(crate) async fn connect(
+        options: RiemannClientOptions,
+        endpoint: Endpoint,
+    ) -> Result<Transport, RiemannClientError> {
+        if let Some(reason) = options.invalid() {
+            return Err(RiemannClientError::InvalidOptions(reason.clone()));
+        }
+
+        #[cfg(unix)]
+        {
+            if options.unix_socket_path().is_some() {
+                return Self::connect_unix(options).await;
+            }
+        }
+
         #[cfg(feature = "tls")]
         {
             if *options.use_tls() {
-                return Self::connect_tls(options).await;
+                return Self::connect_tls(options, endpoint).await;
             }
         }
 
         if *options.use_udp() {
-            Self::connect_udp(options).await
+            Self::connect_udp(options, endpoint).await
         } else {
-            Self::connect_plain(options).await
+            Self::connect_plain(options, endpoint).await
         }
     }
 
-    async fn connect_udp(options: RiemannClientOptions) -> Result<Transport, io::Error> {
-        let udp_transport = UdpTransportInner::new(&options).await?;
+    async fn connect_udp(
+        options: RiemannClientOptions,
+        endpoint: Endpoint,
+    ) -> Result<Transport, RiemannClientError> {
+        let udp_transport =
+            UdpTransportInner::new(&endpoint, *options.udp_max_datagram_size()).await?;
         Ok(Transport::Udp(udp_transport))
     }
 
-    async fn connect_plain(options: RiemannClientOptions) -> Result<Transport, io::Error> {
-        let addr = options.to_socket_addr_string();
-        timeout(
-            Duration::from_millis(*options.connect_timeout_ms()),
-            TcpStream::connect(addr),
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
-        .await?
-        .and_then(|socket| {
-            socket.set_nodelay(true)?;
+    async fn connect_plain(
+        options: RiemannClientOptions,
+        endpoint: Endpoint,
+    ) -> Result<Transport, RiemannClientError> {
+        let endpoint = endpoint.to_string();
+        let socket =
+            connect_with_timeout(&options, &endpoint, TcpStream::connect(&endpoint)).await?;
+        set_nodelay(&socket, &endpoint)?;
 
-            let conn = TcpTransportInner::setup_conn(socket);
-            Ok(Transport::Plain(conn))
-        })
+        let conn = TcpTransportInner::setup_conn(socket, *options.max_in_flight(), endpoint);
+        Ok(Transport::Plain(conn))
+    }
+
+    #[cfg(unix)]
+    async fn connect_unix(options: RiemannClientOptions) -> Result<Transport, RiemannClientError> {
+        let path = options
+            .unix_socket_path()
+            .clone()
+            .ok_or_else(|| RiemannClientError::InvalidOptions("No unix socket path".to_owned()))?;
+        let endpoint = path.display().to_string();
+        let socket = connect_with_timeout(&options, &endpoint, UnixStream::connect(&path)).await?;
+
+        let conn = TcpTransportInner::setup_conn(socket, *options.max_in_flight(), endpoint);
+        Ok(Transport::Unix(conn))
     }
 
     #[cfg(feature = "tls")]
-    async fn connect_tls(options: RiemannClientOptions) -> Result<Transport, io::Error> {
-        let addr = options.to_socket_addr_string();
-        timeout(
-            Duration::from_millis(*options.connect_timeout_ms()),
-            TcpStream::connect(addr),
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
-        .await?
-        .and_then(|socket| {
-            socket.set_nodelay(true)?;
-
-            setup_tls_client(socket, &options)
-        })?
-        .await
-        .map(|socket| {
-            let conn = TcpTransportInner::setup_conn(socket);
-            Transport::Tls(conn)
-        })
+    async fn connect_tls(
+        options: RiemannClientOptions,
+        endpoint: Endpoint,
+    ) -> Result<Transport, RiemannClientError> {
+        let (tls_config, generation) = current_tls_config(&options)?;
+        let host = endpoint.host().clone();
+        let endpoint = endpoint.to_string();
+        let socket =
+            connect_with_timeout(&options, &endpoint, TcpStream::connect(&endpoint)).await?;
+        set_nodelay(&socket, &endpoint)?;
+
+        let tls_error = |source| RiemannClientError::tls(endpoint.clone(), source);
+        let handshake = setup_tls_client(socket, tls_config, &options, &host).map_err(tls_error)?;
+        let socket = handshake.await.map_err(tls_error)?;
+
+        let conn =
+            TcpTransportInner::setup_conn(socket, *options.max_in_flight(), endpoint.clone());
+        Ok(Transport::Tls(conn, generation))
+    }
+
+    /// `host:port` of the server, or the path of its unix socket
+    pub(crate) fn endpoint(&self) -> &str {
+        match self {
+            Transport::Plain(ref inner) => &inner.endpoint,
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref inner, _) => &inner.endpoint,
+            #[cfg(unix)]
+            Transport::Unix(ref inner) => &inner.endpoint,
+            Transport::Udp(ref inner) => &inner.endpoint,
+        }
+    }
+
+    /// Whether this connection was made with a TLS config that has since been
+    /// replaced.
+    pub(crate) fn is_stale(&self, options: &RiemannClientOptions) -> bool {
+        #[cfg(feature = "tls")]
+        {
+            if let (Transport::Tls(_, generation), Some(source)) = (self, options.tls_source()) {
+                return *generation != source.generation();
+            }
+        }
+        let _ = options;
+        false
+    }
+
+    /// Close the connection cleanly, see `TcpTransportInner::close`.
+    pub(crate) async fn close(self) {
+        match self {
+            Transport::Plain(inner) => inner.close().await,
+            #[cfg(feature = "tls")]
+            Transport::Tls(inner, _) => inner.close().await,
+            #[cfg(unix)]
+            Transport::Unix(inner) => inner.close().await,
+            Transport::Udp(_) => {}
+        }
     }
 
     pub(crate) async fn send_events(
         &self,
         events: Vec<Event>,
         socket_timeout: u64,
-    ) -> Result<Msg, io::Error> {
+    ) -> Result<Msg, RiemannClientError> {
+        // udp splits the events over several messages itself
+        if let Transport::Udp(ref inner) = self {
+            inner.send_without_response(events).await?;
+            let ok_msg = Msg {
+                ok: Some(true),
+                ..Default::default()
+            };
+            return Ok(ok_msg);
+        }
+
         let msg = Msg {
             events,
             ..Default::default()
         };
-
-        match self {
-            Transport::Plain(ref inner) => inner.send_for_response(msg, socket_timeout).await,
-            #[cfg(feature = "tls")]
-            Transport::Tls(ref inner) => inner.send_for_response(msg, socket_timeout).await,
-            Transport::Udp(ref inner) => {
-                inner.send_without_response(msg).await?;
-                let ok_msg = Msg {
-                    ok: Some(true),
-                    ..Default::default()
-                };
-                Ok(ok_msg)
-            }
-        }
+        self.send_for_response(msg, Operation::SendEvents, socket_timeout)
+            .await
     }
 
-    pub(crate) async fn query(&self, query: Query, socket_timeout: u64) -> Result<Msg, io::Error> {
+    pub(crate) async fn query(
+        &self,
+        query: Query,
+        socket_timeout: u64,
+    ) -> Result<Msg, RiemannClientError> {
         let msg = Msg {
             query: Some(query),
             ..Default::default()
         };
+        self.send_for_response(msg, Operation::Query, socket_timeout)
+            .await
+    }
 
+    async fn send_for_response(
+        &self,
+        msg: Msg,
+        operation: Operation,
+        socket_timeout: u64,
+    ) -> Result<Msg, RiemannClientError> {
         match self {
-            Transport::Plain(ref inner) => inner.send_for_response(msg, socket_timeout).await,
+            Transport::Plain(ref inner) => {
+                inner
+                    .send_for_response(msg, operation, socket_timeout)
+                    .await
+            }
             #[cfg(feature = "tls")]
-            Transport::Tls(ref inner) => inner.send_for_response(msg, socket_timeout).await,
-            Transport::Udp(_) => Err(io::Error::new(io::ErrorKind::Other, "Unsupported.")),
+            Transport::Tls(ref inner, _) => {
+                inner
+                    .send_for_response(msg, operation, socket_timeout)
+                    .await
+            }
+            #[cfg(unix)]
+            Transport::Unix(ref inner) => {
+                inner
+                    .send_for_response(msg, operation, socket_timeout)
+                    .await
+            }
+            Transport::Udp(_) => Err(RiemannClientError::Unsupported {
+                operation,
+                transport: "udp",
+            }),
         }
     }
 }
+
+/// Disable Nagle's algorithm on `socket`, its failure fails the connection.
+fn set_nodelay(socket: &TcpStream, endpoint: &str) -> Result<(), RiemannClientError> {
+    socket
+        .set_nodelay(true)
+        .map_err(|source| RiemannClientError::Connect {
+            endpoint: endpoint.to_owned(),
+            source,
+        })
+}
+
+/// Open a socket with `connect`, within the connect timeout of `options`.
+async fn connect_with_timeout<S, F>(
+    options: &RiemannClientOptions,
+    endpoint: &str,
+    connect: F,
+) -> Result<S, RiemannClientError>
+where
+    F: Future<Output = io::Result<S>>,
+{
+    let connect_timeout = Duration::from_millis(*options.connect_timeout_ms());
+    match timeout(connect_timeout, connect).await {
+        Ok(Ok(socket)) => Ok(socket),
+        Ok(Err(source)) => Err(RiemannClientError::Connect {
+            endpoint: endpoint.to_owned(),
+            source,
+        }),
+        Err(_) => Err(RiemannClientError::ConnectTimeout {
+            endpoint: endpoint.to_owned(),
+            timeout: connect_timeout,
+        }),
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::str::FromStr;
+
+use url::{Host, Url};
+
+use crate::error::RiemannClientError;
+use crate::options::{Protocol, RiemannClientOptions, RiemannClientOptionsBuilder};
+
+const TCP: &str = "riemann+tcp";
+const UDP: &str = "riemann+udp";
+const TLS: &str = "riemann+tls";
+const UNIX: &str = "unix";
+
+/// Query parameters whose value is not shown when formatting
+const REDACTED_PARAMS: &[&str] = &["key"];
+
+impl RiemannClientOptionsBuilder {
+    /// Start from a connection URL.
+    ///
+    /// The scheme selects the transport: `riemann+tcp://host:5555`,
+    /// `riemann+udp://host:5555`, `riemann+tls://host:5554` or
+    /// `unix:///run/riemann.sock`. The port defaults to 5555, or 5554 for
+    /// TLS.
+    ///
+    /// Supported query parameters are `connect_timeout_ms` and
+    /// `socket_timeout_ms`, and for TLS `ca`, `cert` and `key` (PEM file
+    /// paths) and `server_name`.
+    pub fn from_url(url: &str) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+        RiemannClientOptionsBuilder::default().with_url(url)
+    }
+
+    /// Apply the settings of a connection URL, see `from_url`, on top of the
+    /// current ones.
+    pub fn with_url(self, url: &str) -> Result<RiemannClientOptionsBuilder, RiemannClientError> {
+        let url = Url::parse(url).map_err(|e| invalid(format!("{}: {}", url, e)))?;
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(invalid("credentials are not supported in the URL"));
+        }
+
+        let mut builder = match url.scheme() {
+            TCP | UDP | TLS => {
+                let host = match url.host() {
+                    Some(Host::Domain(host)) if !host.is_empty() => host.to_owned(),
+                    Some(Host::Ipv4(ip)) => ip.to_string(),
+                    Some(Host::Ipv6(ip)) => ip.to_string(),
+                    _ => return Err(invalid("missing host")),
+                };
+                if !matches!(url.path(), "" | "/") {
+                    return Err(invalid(format!("unexpected path {}", url.path())));
+                }
+                let (protocol, default_port) = match url.scheme() {
+                    TLS => (Protocol::Tls, 5554),
+                    UDP => (Protocol::Udp, 5555),
+                    _ => (Protocol::Tcp, 5555),
+                };
+                self.set_protocol(protocol, None)?
+                    .host(host)
+                    .port(url.port().unwrap_or(default_port))
+            }
+            UNIX => {
+                if url.host_str().map(|h| !h.is_empty()).unwrap_or(false) {
+                    return Err(invalid("a unix socket URL has no host"));
+                }
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| invalid(format!("invalid socket path {}", url.path())))?;
+                self.set_protocol(Protocol::Unix, Some(path))?
+            }
+            scheme => return Err(invalid(format!("unsupported scheme {}", scheme))),
+        };
+
+        for (key, value) in url.query_pairs() {
+            builder = match key.as_ref() {
+                "connect_timeout_ms" => {
+                    builder.connect_timeout_ms(parse_param::<u64>(&key, &value)?)
+                }
+                "socket_timeout_ms" => builder.socket_timeout_ms(parse_param::<u64>(&key, &value)?),
+                "ca" | "cert" | "key" | "server_name" if url.scheme() == TLS => {
+                    tls_param(builder, &key, value.into_owned())
+                }
+                _ => return Err(invalid(format!("unsupported parameter {}", key))),
+            };
+        }
+
+        Ok(builder)
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_param(
+    builder: RiemannClientOptionsBuilder,
+    key: &str,
+    value: String,
+) -> RiemannClientOptionsBuilder {
+    match key {
+        "ca" => builder.tls_ca_file(value),
+        "cert" => builder.tls_cert_file(value),
+        "key" => builder.tls_key_file(value),
+        _ => builder.tls_server_name(value),
+    }
+}
+
+// unreachable, a TLS URL is refused without the tls feature
+#[cfg(not(feature = "tls"))]
+fn tls_param(
+    builder: RiemannClientOptionsBuilder,
+    _key: &str,
+    _value: String,
+) -> RiemannClientOptionsBuilder {
+    builder
+}
+
+fn parse_param<T: FromStr>(key: &str, value: &str) -> Result<T, RiemannClientError> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid value {} for {}", value, key)))
+}
+
+fn invalid<S: Into<String>>(reason: S) -> RiemannClientError {
+    RiemannClientError::InvalidOptions(reason.into())
+}
+
+impl FromStr for RiemannClientOptions {
+    type Err = RiemannClientError;
+
+    /// Parse options from a connection URL, see
+    /// `RiemannClientOptionsBuilder::from_url`.
+    fn from_str(s: &str) -> Result<RiemannClientOptions, RiemannClientError> {
+        RiemannClientOptionsBuilder::from_url(s)?.try_build()
+    }
+}
+
+impl RiemannClientOptions {
+    /// Format these options as a connection URL, with the primary endpoint
+    /// and the settings a URL can carry. The location of the TLS private key
+    /// is redacted.
+    pub fn to_url(&self) -> String {
+        let mut params: Vec<(&str, String)> = Vec::new();
+        let defaults = RiemannClientOptions::default();
+        if self.connect_timeout_ms() != defaults.connect_timeout_ms() {
+            params.push(("connect_timeout_ms", self.connect_timeout_ms().to_string()));
+        }
+        if self.socket_timeout_ms() != defaults.socket_timeout_ms() {
+            params.push(("socket_timeout_ms", self.socket_timeout_ms().to_string()));
+        }
+
+        let mut url = match self.unix_url() {
+            Some(url) => url,
+            None => {
+                let scheme = if self.tls_params(&mut params) {
+                    TLS
+                } else if *self.use_udp() {
+                    UDP
+                } else {
+                    TCP
+                };
+                let host = if self.host().contains(':') {
+                    format!("[{}]", self.host())
+                } else {
+                    self.host().clone()
+                };
+                Url::parse(&format!("{}://{}:{}", scheme, host, self.port()))
+                    .expect("endpoint is a valid URL")
+            }
+        };
+
+        if !params.is_empty() {
+            let mut query = url.query_pairs_mut();
+            for (key, value) in params {
+                if REDACTED_PARAMS.contains(&key) {
+                    query.append_pair(key, "redacted");
+                } else {
+                    query.append_pair(key, &value);
+                }
+            }
+        }
+        url.into()
+    }
+
+    #[cfg(unix)]
+    fn unix_url(&self) -> Option<Url> {
+        self.unix_socket_path().as_ref().map(|path| {
+            let mut url = Url::parse("unix://").expect("valid base URL");
+            url.set_path(&path.to_string_lossy());
+            url
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn unix_url(&self) -> Option<Url> {
+        None
+    }
+
+    /// Add the TLS query parameters, returns whether TLS is used.
+    #[cfg(feature = "tls")]
+    fn tls_params(&self, params: &mut Vec<(&str, String)>) -> bool {
+        if !*self.use_tls() {
+            return false;
+        }
+        let files = [
+            ("ca", self.tls_ca_file()),
+            ("cert", self.tls_cert_file()),
+            ("key", self.tls_key_file()),
+        ];
+        for (key, file) in files.iter() {
+            if let Some(file) = file {
+                params.push((key, file.to_string_lossy().into_owned()));
+            }
+        }
+        if let Some(name) = self.tls_server_name() {
+            params.push(("server_name", name.clone()));
+        }
+        true
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn tls_params(&self, _params: &mut Vec<(&str, String)>) -> bool {
+        false
+    }
+}
+
+impl fmt::Display for RiemannClientOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_url())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::error::{InvalidField, ValidationError};
+use crate::event::EventBuilder;
+use crate::protos::riemann::Event;
+
+/// Longest description accepted, in bytes
+pub const MAX_DESCRIPTION_LEN: usize = 64 * 1024;
+
+/// What `RiemannClient::send_events` does with invalid events, see
+/// `Event::validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Send events as they are
+    Off,
+    /// Fail the whole batch with `RiemannClientError::Validation`
+    Reject,
+    /// Fix invalid events with `Event::sanitize` and send them
+    Sanitize,
+}
+
+impl Event {
+    /// Check the event for what riemann rejects or mangles: attributes with
+    /// an empty key, empty tags, NaN or infinite metrics, a negative, NaN or
+    /// infinite ttl, and descriptions over `MAX_DESCRIPTION_LEN` bytes.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut fields = Vec::new();
+
+        if self.metric_d.map(|d| !d.is_finite()).unwrap_or(false) {
+            fields.push(InvalidField::new("metric_d", "is not finite"));
+        }
+        if self.metric_f.map(|f| !f.is_finite()).unwrap_or(false) {
+            fields.push(InvalidField::new("metric_f", "is not finite"));
+        }
+        if self.ttl.map(|t| !valid_ttl(t)).unwrap_or(false) {
+            fields.push(InvalidField::new("ttl", "is negative or not finite"));
+        }
+        if let Some(ref description) = self.description {
+            if description.len() > MAX_DESCRIPTION_LEN {
+                fields.push(InvalidField::new("description", "is too long"));
+            }
+        }
+        for (i, tag) in self.tags.iter().enumerate() {
+            if tag.is_empty() {
+                fields.push(InvalidField::new(format!("tags[{}]", i), "is empty"));
+            }
+        }
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if attribute.key.is_empty() {
+                fields.push(InvalidField::new(
+                    format!("attributes[{}].key", i),
+                    "is empty",
+                ));
+            }
+        }
+
+        if fields.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::new(fields))
+        }
+    }
+
+    /// Make the event valid: invalid metrics and ttl are removed, as are
+    /// empty tags and attributes with an empty key, and the description is
+    /// truncated.
+    pub fn sanitize(&mut self) {
+        if self.metric_d.map(|d| !d.is_finite()).unwrap_or(false) {
+            self.metric_d = None;
+        }
+        if self.metric_f.map(|f| !f.is_finite()).unwrap_or(false) {
+            self.metric_f = None;
+        }
+        if self.ttl.map(|t| !valid_ttl(t)).unwrap_or(false) {
+            self.ttl = None;
+        }
+        if let Some(ref mut description) = self.description {
+            if description.len() > MAX_DESCRIPTION_LEN {
+                let mut end = MAX_DESCRIPTION_LEN;
+                while !description.is_char_boundary(end) {
+                    end -= 1;
+                }
+                description.truncate(end);
+            }
+        }
+        self.tags.retain(|tag| !tag.is_empty());
+        self.attributes
+            .retain(|attribute| !attribute.key.is_empty());
+    }
+}
+
+fn valid_ttl(ttl: f32) -> bool {
+    ttl.is_finite() && ttl >= 0.0
+}
+
+impl EventBuilder {
+    /// Build the event, failing if it is invalid, see `Event::validate`.
+    pub fn try_build(self) -> Result<Event, ValidationError> {
+        let event = self.build();
+        event.validate()?;
+        Ok(event)
+    }
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+use rustmann::{
+    BatchError, BatchOptionsBuilder, BatchSender, EventBuilder, OverflowPolicy, RiemannClient,
+    RiemannClientOptionsBuilder,
+};
+
+use common::MockServer;
+
+fn client_for(server: &MockServer) -> Arc<RiemannClient> {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .build();
+    Arc::new(RiemannClient::new(&options))
+}
+
+#[tokio::test]
+async fn test_flush_by_size_and_on_close() {
+    let server = MockServer::start().await;
+    let options = BatchOptionsBuilder::default()
+        .max_batch_size(10_usize)
+        .max_delay_ms(60_000_u64)
+        .build();
+    let (sender, reports) = BatchSender::new(client_for(&server), &options);
+
+    for i in 0..25 {
+        sender
+            .send(EventBuilder::new().service(format!("s{}", i)).build())
+            .await
+            .unwrap();
+    }
+    sender.close().await;
+
+    let reports: Vec<_> = reports.collect().await;
+    let sizes: Vec<usize> = reports.iter().map(|r| *r.events()).collect();
+    assert_eq!(vec![10, 10, 5], sizes);
+    assert!(reports.iter().all(|r| r.result().is_ok()));
+    assert_eq!(25, server.events());
+    assert_eq!(3, server.messages());
+}
+
+#[tokio::test]
+async fn test_flush_by_delay() {
+    let server = MockServer::start().await;
+    let options = BatchOptionsBuilder::default()
+        .max_batch_size(100_usize)
+        .max_delay_ms(50_u64)
+        .build();
+    let (sender, mut reports) = BatchSender::new(client_for(&server), &options);
+
+    sender.try_send(EventBuilder::new().build()).unwrap();
+    sender.try_send(EventBuilder::new().build()).unwrap();
+
+    let report = reports.next().await.unwrap();
+    assert_eq!(2, *report.events());
+    assert_eq!(2, server.events());
+}
+
+#[tokio::test]
+async fn test_overflow_policies() {
+    let server = MockServer::start().await;
+    let client = client_for(&server);
+
+    let options = BatchOptionsBuilder::default()
+        .max_batch_size(2_usize)
+        .queue_capacity(2_usize)
+        .max_delay_ms(60_000_u64)
+        .overflow_policy(OverflowPolicy::Block);
+    let (sender, _reports) = BatchSender::new(client.clone(), &options.build());
+    // the flush task has not had a chance to run on this single threaded
+    // runtime yet
+    sender.try_send(EventBuilder::new().build()).unwrap();
+    sender.try_send(EventBuilder::new().build()).unwrap();
+    assert!(matches!(
+        sender.try_send(EventBuilder::new().build()),
+        Err(BatchError::Full(_))
+    ));
+
+    let options = BatchOptionsBuilder::default()
+        .max_batch_size(2_usize)
+        .queue_capacity(2_usize)
+        .max_delay_ms(60_000_u64)
+        .overflow_policy(OverflowPolicy::DropOldest)
+        .build();
+    let (sender, _reports) = BatchSender::new(client, &options);
+    for i in 0..5 {
+        sender
+            .try_send(EventBuilder::new().service(format!("s{}", i)).build())
+            .unwrap();
+    }
+    assert_eq!(2, sender.queued());
+    assert_eq!(3, sender.dropped());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rustmann::blocking::RiemannClient;
+use rustmann::{EventBuilder, RiemannClientError, RiemannClientOptionsBuilder};
+
+use common::{unused_port, MockServer};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_send_and_query() {
+    let server = MockServer::start().await;
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .build();
+
+    let events = tokio::task::spawn_blocking(move || {
+        let client = Arc::new(RiemannClient::new(&options).unwrap());
+        let senders: Vec<_> = (0..4)
+            .map(|i| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    client
+                        .send_events(vec![EventBuilder::new()
+                            .service(format!("blocking {}", i))
+                            .build()])
+                        .unwrap()
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        client.send_query("true").unwrap()
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(4, events.len());
+    assert_eq!(4, server.events());
+}
+
+#[test]
+fn test_connection_error() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(unused_port())
+        .build();
+    let client = RiemannClient::new(&options).unwrap();
+
+    let result = client.send_events(vec![EventBuilder::new().build()]);
+    assert!(matches!(result, Err(RiemannClientError::Connect { .. })));
+}
+
+type Sent = Arc<Mutex<Option<Result<(), RiemannClientError>>>>;
+
+struct ReportOnDrop(RiemannClient, Sent);
+
+impl Drop for ReportOnDrop {
+    fn drop(&mut self) {
+        let result = self.0.send_events(vec![EventBuilder::new().build()]);
+        *self.1.lock().unwrap() = Some(result);
+    }
+}
+
+#[test]
+fn test_send_from_drop_during_runtime_shutdown() {
+    // the server keeps running on its own runtime
+    let server_runtime = tokio::runtime::Runtime::new().unwrap();
+    let server = server_runtime.block_on(MockServer::start());
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .build();
+
+    // owned by a task that is dropped with the runtime
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let sent = Sent::default();
+    let reporter = ReportOnDrop(RiemannClient::new(&options).unwrap(), sent.clone());
+    runtime.spawn(async move {
+        let _reporter = reporter;
+        futures::future::pending::<()>().await
+    });
+    drop(runtime);
+
+    assert!(matches!(*sent.lock().unwrap(), Some(Ok(()))));
+    assert_eq!(1, server.events());
+}
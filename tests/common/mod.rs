@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use rustmann::protos::riemann::Msg;
+
+/// A minimal riemann tcp server that records every message it receives and
+/// answers `ok`. Queries are answered with all events received so far.
+pub struct MockServer {
+    pub addr: SocketAddr,
+    pub received: Arc<Mutex<Vec<Msg>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub async fn start() -> MockServer {
+        MockServer::start_on(0).await
+    }
+
+    pub async fn start_on(port: u16) -> MockServer {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let recv = received.clone();
+        let conns = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                conns.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve(socket, recv.clone()));
+            }
+        });
+
+        MockServer {
+            addr,
+            received,
+            connections,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    pub fn events(&self) -> usize {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.events.len())
+            .sum()
+    }
+
+    /// Number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn messages(&self) -> usize {
+        self.received.lock().unwrap().len()
+    }
+}
+
+/// A local port with nothing listening on it.
+pub fn unused_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Answer riemann requests on `socket`, storing received events in
+/// `received`.
+pub async fn serve<S>(mut socket: S, received: Arc<Mutex<Vec<Msg>>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let len = match socket.read_u32().await {
+            Ok(len) => len as usize,
+            Err(_) => return,
+        };
+        let mut buf = vec![0u8; len];
+        if socket.read_exact(&mut buf).await.is_err() {
+            return;
+        }
+        let msg = Msg::decode(buf.as_slice()).unwrap();
+
+        let response = if msg.query.is_some() {
+            let events = received
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|m| m.events.clone())
+                .collect();
+            Msg {
+                ok: Some(true),
+                events,
+                ..Default::default()
+            }
+        } else {
+            received.lock().unwrap().push(msg);
+            Msg {
+                ok: Some(true),
+                ..Default::default()
+            }
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(response.encoded_len() as u32).to_be_bytes());
+        response.encode(&mut out).unwrap();
+        if socket.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
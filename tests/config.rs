@@ -0,0 +1,195 @@
+use std::env;
+
+use rustmann::{
+    Protocol, RiemannClientConfig, RiemannClientError, RiemannClientOptions,
+    RiemannClientOptionsBuilder,
+};
+
+/// Set variables under a prefix no other test uses
+fn set_vars(prefix: &str, vars: &[(&str, &str)]) {
+    for (name, value) in vars {
+        env::set_var(format!("{}_{}", prefix, name), value);
+    }
+}
+
+#[test]
+fn test_from_env() {
+    set_vars(
+        "RUSTMANN_TEST_ENV",
+        &[
+            ("HOST", "riemann.example.com"),
+            ("PORT", "5556"),
+            ("PROTOCOL", "UDP"),
+            ("POOL_SIZE", "4"),
+            ("SOCKET_TIMEOUT_MS", ""),
+        ],
+    );
+
+    let config = RiemannClientConfig::from_env("RUSTMANN_TEST_ENV").unwrap();
+    assert_eq!(Some("riemann.example.com".to_owned()), config.host);
+    assert_eq!(Some(5556), config.port);
+    assert_eq!(Some(Protocol::Udp), config.protocol);
+    assert_eq!(Some(4), config.pool_size);
+    assert_eq!(None, config.socket_timeout_ms);
+
+    let options = RiemannClientOptions::load(None, "RUSTMANN_TEST_ENV").unwrap();
+    assert_eq!("riemann.example.com", options.host());
+    assert_eq!(5556, *options.port());
+    assert!(*options.use_udp());
+    assert_eq!(4, *options.pool_size());
+    assert_eq!(
+        *RiemannClientOptions::default().socket_timeout_ms(),
+        *options.socket_timeout_ms()
+    );
+}
+
+#[test]
+fn test_env_errors() {
+    set_vars("RUSTMANN_TEST_BAD_PORT", &[("PORT", "http")]);
+    match RiemannClientConfig::from_env("RUSTMANN_TEST_BAD_PORT") {
+        Err(RiemannClientError::InvalidOptions(e)) => {
+            assert!(e.contains("RUSTMANN_TEST_BAD_PORT_PORT"), "{}", e)
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    set_vars("RUSTMANN_TEST_BAD_PROTOCOL", &[("PROTOCOL", "sctp")]);
+    assert!(RiemannClientConfig::from_env("RUSTMANN_TEST_BAD_PROTOCOL").is_err());
+}
+
+#[test]
+fn test_precedence() {
+    let file = RiemannClientConfig {
+        url: Some("riemann+tcp://file.example.com:5557?connect_timeout_ms=100".to_owned()),
+        socket_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    set_vars(
+        "RUSTMANN_TEST_PRECEDENCE",
+        &[("HOST", "env.example.com"), ("SOCKET_TIMEOUT_MS", "300")],
+    );
+
+    let options = RiemannClientOptions::load(Some(&file), "RUSTMANN_TEST_PRECEDENCE").unwrap();
+    assert_eq!("env.example.com", options.host());
+    assert_eq!(5557, *options.port());
+    assert_eq!(100, *options.connect_timeout_ms());
+    assert_eq!(300, *options.socket_timeout_ms());
+
+    // setters applied afterwards win over both
+    let options = RiemannClientOptionsBuilder::default()
+        .with_config(&file)
+        .unwrap()
+        .with_env("RUSTMANN_TEST_PRECEDENCE")
+        .unwrap()
+        .port(5558u16)
+        .try_build()
+        .unwrap();
+    assert_eq!("env.example.com", options.host());
+    assert_eq!(5558, *options.port());
+}
+
+#[test]
+fn test_merge() {
+    let base = RiemannClientConfig {
+        host: Some("a".to_owned()),
+        port: Some(1),
+        ..Default::default()
+    };
+    let merged = base.merge(RiemannClientConfig {
+        port: Some(2),
+        max_in_flight: Some(16),
+        ..Default::default()
+    });
+    assert_eq!(Some("a".to_owned()), merged.host);
+    assert_eq!(Some(2), merged.port);
+    assert_eq!(Some(16), merged.max_in_flight);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket_implies_protocol() {
+    let config = RiemannClientConfig {
+        unix_socket: Some("/run/riemann.sock".into()),
+        ..Default::default()
+    };
+    let options = RiemannClientOptionsBuilder::default()
+        .with_config(&config)
+        .unwrap()
+        .try_build()
+        .unwrap();
+    assert_eq!(
+        Some(std::path::Path::new("/run/riemann.sock")),
+        options.unix_socket_path().as_deref()
+    );
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls_settings_require_tls() {
+    let config = RiemannClientConfig {
+        tls_ca: Some("tests/certs/ca.pem".into()),
+        ..Default::default()
+    };
+    let result = RiemannClientOptionsBuilder::default()
+        .with_config(&config)
+        .unwrap()
+        .try_build();
+    assert!(matches!(result, Err(RiemannClientError::InvalidOptions(_))));
+
+    let config = RiemannClientConfig {
+        protocol: Some(Protocol::Tls),
+        ..config
+    };
+    let options = RiemannClientOptionsBuilder::default()
+        .with_config(&config)
+        .unwrap()
+        .try_build()
+        .unwrap();
+    assert!(*options.use_tls());
+    assert_eq!(5554, *options.port());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_deserialize_section() {
+    #[derive(serde::Deserialize)]
+    struct AppConfig {
+        riemann: RiemannClientConfig,
+    }
+
+    let app: AppConfig = toml::from_str(
+        r#"
+        [riemann]
+        host = "riemann.example.com"
+        protocol = "udp"
+        connect_timeout_ms = 250
+        "#,
+    )
+    .unwrap();
+    assert_eq!(Some(Protocol::Udp), app.riemann.protocol);
+    assert_eq!(Some(250), app.riemann.connect_timeout_ms);
+
+    let unknown: Result<AppConfig, _> = toml::from_str("[riemann]\nhots = \"typo\"\n");
+    assert!(unknown.is_err());
+}
+
+#[test]
+fn test_env_url_overrides_file_fields() {
+    let file = RiemannClientConfig {
+        host: Some("file.example.com".to_owned()),
+        port: Some(5557),
+        connect_timeout_ms: Some(100),
+        ..Default::default()
+    };
+    set_vars(
+        "RUSTMANN_TEST_ENV_URL",
+        &[("URL", "riemann+udp://env.example.com:5558")],
+    );
+
+    let options = RiemannClientOptions::load(Some(&file), "RUSTMANN_TEST_ENV_URL").unwrap();
+    assert_eq!("env.example.com", options.host());
+    assert_eq!(5558, *options.port());
+    assert!(*options.use_udp());
+    // settings the url does not carry are kept from the file
+    assert_eq!(100, *options.connect_timeout_ms());
+}
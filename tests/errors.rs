@@ -0,0 +1,180 @@
+mod common;
+
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use rustmann::protos::riemann::Msg;
+use rustmann::{
+    EventBuilder, Operation, RetryPolicy, RiemannClient, RiemannClientError,
+    RiemannClientOptionsBuilder,
+};
+
+use common::unused_port;
+
+/// A server that reads one request on each connection and answers it with
+/// the bytes written by `respond`, if any.
+async fn server<F>(respond: F) -> u16
+where
+    F: Fn(&mut Vec<u8>) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let respond = std::sync::Arc::new(respond);
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                read_request(&mut socket).await;
+                let mut out = Vec::new();
+                respond(&mut out);
+                socket.write_all(&out).await.unwrap();
+                // hold the connection open
+                let _ = socket.read_u8().await;
+            });
+        }
+    });
+    port
+}
+
+async fn read_request(socket: &mut TcpStream) {
+    let len = socket.read_u32().await.unwrap() as usize;
+    let mut buf = vec![0u8; len];
+    socket.read_exact(&mut buf).await.unwrap();
+}
+
+fn client(port: u16) -> RiemannClient {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(port)
+        .socket_timeout_ms(200u64)
+        .build();
+    RiemannClient::new(&options)
+}
+
+async fn send(client: &RiemannClient) -> RiemannClientError {
+    client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .unwrap_err()
+}
+
+#[tokio::test]
+async fn test_connect_error() {
+    let port = unused_port();
+    match send(&client(port)).await {
+        RiemannClientError::Connect { ref endpoint, .. } => {
+            assert_eq!(format!("127.0.0.1:{}", port), *endpoint)
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn test_response_timeout() {
+    let port = server(|_| {}).await;
+    let e = send(&client(port)).await;
+    assert!(e.is_retryable());
+    assert!(matches!(
+        e,
+        RiemannClientError::ResponseTimeout {
+            operation: Operation::SendEvents,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_server_error() {
+    let port = server(|out| {
+        let msg = Msg {
+            ok: Some(false),
+            error: Some("parse error".to_owned()),
+            ..Default::default()
+        };
+        out.extend_from_slice(&(msg.encoded_len() as u32).to_be_bytes());
+        msg.encode(out).unwrap();
+    })
+    .await;
+    let client = client(port);
+
+    let e = client.send_query("bad query").await.unwrap_err();
+    assert!(!e.is_retryable());
+    match e {
+        RiemannClientError::Server {
+            operation: Operation::Query,
+            ref message,
+            ..
+        } => assert_eq!("parse error", message),
+        e => panic!("unexpected {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn test_protocol_error() {
+    let port = server(|out| out.extend_from_slice(&[0, 0, 0, 2, 0xff, 0xff])).await;
+    let e = send(&client(port)).await;
+    assert!(!e.is_retryable());
+    assert!(matches!(e, RiemannClientError::Protocol { .. }));
+}
+
+#[tokio::test]
+async fn test_connection_closed() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        read_request(&mut socket).await;
+    });
+
+    let e = send(&client(port)).await;
+    assert!(e.is_retryable());
+    assert!(matches!(
+        e,
+        RiemannClientError::ConnectionClosed {
+            operation: Operation::SendEvents,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_query_over_udp_is_unsupported() {
+    let options = RiemannClientOptionsBuilder::default().use_udp(true).build();
+    let e = RiemannClient::new(&options)
+        .send_query("true")
+        .await
+        .unwrap_err();
+    assert!(!e.is_retryable());
+    assert!(matches!(
+        e,
+        RiemannClientError::Unsupported {
+            operation: Operation::Query,
+            transport: "udp",
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_open_circuit_fails_fast() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(unused_port())
+        .circuit_breaker_threshold(1_u32)
+        .circuit_breaker_cooldown_ms(60_000_u64)
+        .build();
+    let client = RiemannClient::new(&options).with_retry_policy(RetryPolicy::new(5).backoff_ms(1));
+
+    // the first failed connection opens the circuit
+    let _ = send(&client).await;
+    let retries = client.retries();
+
+    let e = send(&client).await;
+    assert!(matches!(e, RiemannClientError::CircuitOpen(_)));
+    assert!(!e.is_retryable());
+    assert_eq!(retries, client.retries());
+}
+
+#[test]
+fn test_local_io_error_is_not_retryable() {
+    let e = RiemannClientError::from(std::io::Error::other("disk full"));
+    assert!(!e.is_retryable());
+}
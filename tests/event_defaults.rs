@@ -0,0 +1,68 @@
+mod common;
+
+use rustmann::{EventBuilder, Metric, RiemannClient, RiemannClientOptionsBuilder};
+
+use common::MockServer;
+
+#[tokio::test]
+async fn test_event_defaults_are_merged() {
+    let server = MockServer::start().await;
+    let defaults = EventBuilder::new()
+        .host("default-host")
+        .ttl(60.0)
+        .metric(1)
+        .add_tag("prod")
+        .add_attribute("region", Some("eu-west-1"))
+        .build();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .event_defaults(defaults)
+        .build();
+    let client = RiemannClient::new(&options);
+
+    client
+        .send_events(vec![
+            EventBuilder::new().service("plain").build(),
+            EventBuilder::new()
+                .service("custom")
+                .host("web-1")
+                .metric(0.5)
+                .add_tag("prod")
+                .add_tag("canary")
+                .add_attribute("region", Some("us-east-1"))
+                .add_attribute("rack", Some("r1"))
+                .build(),
+        ])
+        .await
+        .unwrap();
+
+    let received = server.received.lock().unwrap();
+    let events = &received[0].events;
+
+    let plain = &events[0];
+    assert_eq!(Some("default-host"), plain.host.as_deref());
+    assert_eq!(Some(60.0), plain.ttl);
+    assert_eq!(Some(Metric::Int(1)), plain.metric());
+    assert_eq!(vec!["prod".to_owned()], plain.tags);
+    assert_eq!("region", plain.attributes[0].key);
+
+    let custom = &events[1];
+    assert_eq!(Some("web-1"), custom.host.as_deref());
+    assert_eq!(Some(60.0), custom.ttl);
+    // the metric is taken as a whole, the default int does not shadow it
+    assert_eq!(Some(Metric::Double(0.5)), custom.metric());
+    assert_eq!(None, custom.metric_sint64);
+    assert_eq!(vec!["prod".to_owned(), "canary".to_owned()], custom.tags);
+    let attributes: Vec<_> = custom
+        .attributes
+        .iter()
+        .map(|a| (a.key.as_str(), a.value.as_deref().unwrap()))
+        .collect();
+    assert_eq!(vec![("region", "us-east-1"), ("rack", "r1")], attributes);
+}
+
+#[test]
+fn test_local_host() {
+    let event = EventBuilder::new().local_host().build();
+    assert!(!event.host.unwrap().is_empty());
+}
@@ -1,4 +1,6 @@
-use rustmann::EventBuilder;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustmann::{EventBuilder, Metric};
 
 #[test]
 fn test_builder() {
@@ -31,3 +33,108 @@ fn test_builder() {
     assert_eq!(2.0, event.metric_f.unwrap());
     assert_eq!(1, event.attributes.len());
 }
+
+#[test]
+fn test_metric() {
+    let event = EventBuilder::new().metric(42).build();
+    assert_eq!(Some(42), event.metric_sint64);
+    assert_eq!(Some(Metric::Int(42)), event.metric());
+
+    // the last metric set replaces the others
+    let event = EventBuilder::new()
+        .metric_sint64(1)
+        .metric_f(2.0)
+        .metric(0.5)
+        .build();
+    assert_eq!(None, event.metric_sint64);
+    assert_eq!(None, event.metric_f);
+    assert_eq!(Some(Metric::Double(0.5)), event.metric());
+
+    let event = EventBuilder::new().metric(1.5f32).build();
+    assert_eq!(Some(Metric::Float(1.5)), event.metric());
+
+    assert_eq!(None, EventBuilder::new().build().metric());
+}
+
+#[test]
+fn test_metric_precedence() {
+    let event = EventBuilder::new()
+        .metric_f(3.0)
+        .metric_d(2.0)
+        .metric_sint64(1)
+        .build();
+    assert_eq!(Some(Metric::Int(1)), event.metric());
+
+    let event = EventBuilder::new().metric_f(3.0).metric_d(2.0).build();
+    assert_eq!(Some(Metric::Double(2.0)), event.metric());
+}
+
+#[test]
+fn test_metric_as_f64() {
+    assert_eq!(Some(3.0), Metric::from(3u8).as_f64());
+    assert_eq!(Some(0.25), Metric::from(0.25f32).as_f64());
+    assert_eq!(Some(9007199254740992.0), Metric::Int(1 << 53).as_f64());
+    assert_eq!(None, Metric::Int((1 << 53) + 1).as_f64());
+    assert_eq!(None, Metric::Int(i64::MAX).as_f64());
+    assert_eq!(Some(-9223372036854775808.0), Metric::Int(i64::MIN).as_f64());
+    assert_eq!("42", Metric::Int(42).to_string());
+}
+
+#[test]
+fn test_timestamp() {
+    let t = UNIX_EPOCH + Duration::from_micros(1_600_000_000_123_456);
+    let event = EventBuilder::new().timestamp(t).build();
+    assert_eq!(Some(1_600_000_000), event.time);
+    assert_eq!(Some(1_600_000_000_123_456), event.time_micros);
+    assert_eq!(Some(t), event.timestamp());
+
+    // before the epoch, seconds round down
+    let t = UNIX_EPOCH - Duration::from_micros(1_500_000);
+    let event = EventBuilder::new().timestamp(t).build();
+    assert_eq!(Some(-2), event.time);
+    assert_eq!(Some(-1_500_000), event.time_micros);
+    assert_eq!(Some(t), event.timestamp());
+
+    // seconds only
+    let event = EventBuilder::new().time(10).build();
+    assert_eq!(
+        Some(UNIX_EPOCH + Duration::from_secs(10)),
+        event.timestamp()
+    );
+    assert_eq!(None, EventBuilder::new().build().timestamp());
+}
+
+#[test]
+fn test_stamp_now() {
+    let before = SystemTime::now();
+    let event = EventBuilder::new().stamp_now().build();
+    let time = event.timestamp().unwrap();
+    assert!(time >= before - Duration::from_micros(1) && time <= SystemTime::now());
+    assert_eq!(
+        event.time.unwrap(),
+        event.time_micros.unwrap().div_euclid(1_000_000)
+    );
+
+    // an explicit time wins
+    let event = EventBuilder::new().stamp_now().time(10).build();
+    assert_eq!(Some(10), event.time);
+    assert_eq!(None, event.time_micros);
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_chrono_timestamp() {
+    let t = chrono::DateTime::from_timestamp(1_600_000_000, 250_000_000).unwrap();
+    let event = EventBuilder::new().timestamp(t).build();
+    assert_eq!(Some(1_600_000_000_250_000), event.time_micros);
+    assert_eq!(Some(t), event.chrono_timestamp());
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn test_offset_date_time() {
+    let t = time::OffsetDateTime::from_unix_timestamp(1_600_000_000).unwrap();
+    let event = EventBuilder::new().timestamp(t).build();
+    assert_eq!(Some(1_600_000_000), event.time);
+    assert_eq!(Some(t), event.offset_date_time());
+}
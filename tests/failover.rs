@@ -0,0 +1,123 @@
+mod common;
+
+use std::time::Duration;
+
+use rustmann::{
+    Endpoint, EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder,
+};
+
+use common::{unused_port, MockServer};
+
+#[tokio::test]
+async fn test_failover_and_failback() {
+    let primary_port = unused_port();
+    let standby = MockServer::start().await;
+
+    let options = RiemannClientOptionsBuilder::default()
+        .endpoint(("127.0.0.1", primary_port))
+        .endpoint(("127.0.0.1", standby.port()))
+        .failback_interval_ms(50_u64)
+        .build();
+    assert_eq!(primary_port, *options.port());
+
+    let client = RiemannClient::new(&options);
+    assert_eq!(
+        &Endpoint::new("127.0.0.1", primary_port),
+        client.active_endpoint()
+    );
+
+    client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .unwrap();
+    assert_eq!(1, standby.events());
+    assert_eq!(standby.port(), *client.active_endpoint().port());
+
+    // primary comes back
+    let primary = MockServer::start_on(primary_port).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    for _ in 0..20 {
+        client
+            .send_events(vec![EventBuilder::new().build()])
+            .await
+            .unwrap();
+        if *client.active_endpoint().port() == primary_port {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(primary_port, *client.active_endpoint().port());
+
+    client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .unwrap();
+    assert!(primary.events() >= 1);
+}
+
+#[tokio::test]
+async fn test_pooled_failback() {
+    let primary_port = unused_port();
+    let standby = MockServer::start().await;
+
+    let options = RiemannClientOptionsBuilder::default()
+        .endpoint(("127.0.0.1", primary_port))
+        .endpoint(("127.0.0.1", standby.port()))
+        .pool_size(2_usize)
+        .failback_interval_ms(50_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    let send = || client.send_events(vec![EventBuilder::new().build()]);
+
+    // both connections fail over
+    for _ in 0..2 {
+        send().await.unwrap();
+    }
+    assert_eq!(2, standby.events());
+    assert_eq!(2, standby.connections());
+
+    let primary = MockServer::start_on(primary_port).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for _ in 0..20 {
+        send().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // every connection is back on the primary
+    let on_standby = standby.events();
+    for _ in 0..10 {
+        send().await.unwrap();
+    }
+    assert_eq!(on_standby, standby.events());
+    assert_eq!(primary_port, *client.active_endpoint().port());
+    assert!(primary.events() >= 10);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker() {
+    let port = unused_port();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(port)
+        .reconnect_backoff_ms(10_u64)
+        .circuit_breaker_threshold(2_u32)
+        .circuit_breaker_cooldown_ms(200_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+
+    for _ in 0..2 {
+        let r = client.send_events(vec![EventBuilder::new().build()]).await;
+        assert!(matches!(r, Err(RiemannClientError::Connect { .. })));
+    }
+    let r = client.send_events(vec![EventBuilder::new().build()]).await;
+    assert!(matches!(r, Err(RiemannClientError::CircuitOpen(_))));
+
+    // half-open after the cooldown, a successful attempt closes the circuit
+    let server = MockServer::start_on(port).await;
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .unwrap();
+    assert_eq!(1, server.events());
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "json")]
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use rustmann::protos::riemann::Event;
+use rustmann::{json, EventBuilder, Metric, RiemannClientError};
+
+fn full_event() -> Event {
+    EventBuilder::new()
+        .host("h1")
+        .service("cpu")
+        .state("ok")
+        .description("load")
+        .metric(42)
+        .add_tag("a")
+        .add_tag("b")
+        .timestamp(UNIX_EPOCH + Duration::from_micros(1_580_473_800_123_456))
+        .ttl(60.0)
+        .add_attribute("rack", Some("r1"))
+        .build()
+}
+
+#[test]
+fn test_riemann_shape() {
+    let value: serde_json::Value = serde_json::from_str(&json::to_string(&full_event())).unwrap();
+    assert_eq!(
+        serde_json::json!({
+            "host": "h1",
+            "service": "cpu",
+            "state": "ok",
+            "description": "load",
+            "metric": 42,
+            "tags": ["a", "b"],
+            "time": "2020-01-31T12:30:00.123456Z",
+            "ttl": 60.0,
+            "rack": "r1",
+        }),
+        value
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    let event = full_event();
+    assert_eq!(event, json::from_str(&json::to_string(&event)).unwrap());
+
+    let event = EventBuilder::new()
+        .metric(0.25)
+        .timestamp(UNIX_EPOCH - Duration::from_micros(1_500_000))
+        .add_attribute("empty", None)
+        .build();
+    assert_eq!(event, json::from_str(&json::to_string(&event)).unwrap());
+
+    // a float metric comes back as a double
+    let event = EventBuilder::new().metric(1.5f32).build();
+    let parsed = json::from_str(&json::to_string(&event)).unwrap();
+    assert_eq!(Some(Metric::Double(1.5)), parsed.metric());
+}
+
+#[test]
+fn test_parse_riemann_json() {
+    let event = json::from_str(
+        r#"{"host":"h1","metric":3,"time":"2020-01-31T20:30:00+08:00","ttl":null,"up":true}"#,
+    )
+    .unwrap();
+    assert_eq!(Some(Metric::Int(3)), event.metric());
+    assert_eq!(Some(1_580_473_800), event.time);
+    assert_eq!(None, event.ttl);
+    assert_eq!(Some("true"), event.attributes[0].value.as_deref());
+
+    let event = json::from_str(r#"{"time":1.5}"#).unwrap();
+    assert_eq!(Some(1_500_000), event.time_micros);
+
+    for bad in &["[]", r#"{"host":1}"#, r#"{"time":"yesterday"}"#, "{"] {
+        assert!(matches!(
+            json::from_str(bad),
+            Err(RiemannClientError::InvalidEvent(_))
+        ));
+    }
+}
+
+#[test]
+fn test_json_lines() {
+    let events = vec![full_event(), EventBuilder::new().service("mem").build()];
+    let mut buf = Vec::new();
+    json::write_lines(&mut buf, &events).unwrap();
+    buf.extend_from_slice(b"\n");
+
+    let text = String::from_utf8(buf).unwrap();
+    assert_eq!(2, text.lines().filter(|l| !l.is_empty()).count());
+
+    let read: Vec<Event> = json::read_lines(text.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(events, read);
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use rustmann::protos::riemann::{Event, Msg};
+use rustmann::{RiemannClient, RiemannClientError, RiemannClientOptionsBuilder};
+
+/// A server that answers each query with a single event whose service is the
+/// query string and whose host is the number of the connection, after a
+/// pause if the query is "slow". Queries on a connection are answered in
+/// order.
+const SLOW_MS: u64 = 500;
+
+async fn echo_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let mut connections = 0;
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let connection = connections.to_string();
+            connections += 1;
+            tokio::spawn(async move {
+                while let Ok(len) = socket.read_u32().await {
+                    let mut buf = vec![0u8; len as usize];
+                    socket.read_exact(&mut buf).await.unwrap();
+                    let query = Msg::decode(buf.as_slice())
+                        .unwrap()
+                        .query
+                        .and_then(|q| q.string)
+                        .unwrap_or_default();
+                    if query == "slow" {
+                        tokio::time::sleep(Duration::from_millis(SLOW_MS)).await;
+                    }
+
+                    let response = Msg {
+                        ok: Some(true),
+                        events: vec![Event {
+                            host: Some(connection.clone()),
+                            service: Some(query),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    };
+                    let mut out = (response.encoded_len() as u32).to_be_bytes().to_vec();
+                    response.encode(&mut out).unwrap();
+                    if socket.write_all(&out).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    port
+}
+
+async fn query(client: &RiemannClient, q: &str) -> Result<String, RiemannClientError> {
+    Ok(query_on(client, q).await?.1)
+}
+
+/// The connection number and the service of the response to `q`
+async fn query_on(client: &RiemannClient, q: &str) -> Result<(String, String), RiemannClientError> {
+    let events = client.send_query(q).await?;
+    Ok((
+        events[0].host.clone().unwrap(),
+        events[0].service.clone().unwrap(),
+    ))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_requests_get_their_own_response() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(echo_server().await)
+        .max_in_flight(8_usize)
+        .build();
+    let client = Arc::new(RiemannClient::new(&options));
+
+    let calls = (0..200).map(|i| {
+        let client = client.clone();
+        async move {
+            let q = format!("q{}", i);
+            assert_eq!(q, query(&client, &q).await.unwrap());
+        }
+    });
+    join_all(calls).await;
+}
+
+#[tokio::test]
+async fn test_timeout_does_not_shift_responses() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(echo_server().await)
+        .socket_timeout_ms(100_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+
+    assert_eq!("before", query(&client, "before").await.unwrap());
+    assert!(query(&client, "slow").await.is_err());
+    // the connection is kept, and the late response is discarded when it
+    // arrives
+    tokio::time::sleep(Duration::from_millis(SLOW_MS)).await;
+    for i in 0..5 {
+        let q = format!("after{}", i);
+        assert_eq!(
+            ("0".to_owned(), q.clone()),
+            query_on(&client, &q).await.unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_late_response_is_discarded_on_a_live_connection() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(echo_server().await)
+        .socket_timeout_ms(400_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    assert_eq!("0", query_on(&client, "before").await.unwrap().0);
+
+    // queries sent behind the slow one, on the same connection, are answered
+    // after its late response
+    let slow = query(&client, "slow");
+    let behind = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        join_all((0..5).map(|i| {
+            let client = &client;
+            async move {
+                let q = format!("behind{}", i);
+                (q.clone(), query_on(client, &q).await.unwrap())
+            }
+        }))
+        .await
+    };
+    let (slow, behind) = tokio::join!(slow, behind);
+
+    assert!(matches!(
+        slow,
+        Err(RiemannClientError::ResponseTimeout { .. })
+    ));
+    for (q, (connection, response)) in behind {
+        assert_eq!("0", connection);
+        assert_eq!(q, response);
+    }
+}
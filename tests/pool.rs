@@ -0,0 +1,55 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use rustmann::{EventBuilder, PoolSelection, RiemannClient, RiemannClientOptionsBuilder};
+
+use common::MockServer;
+
+#[tokio::test]
+async fn test_warm_up_opens_all_connections() {
+    let server = MockServer::start().await;
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .pool_size(4_usize)
+        .pool_warm_up(true)
+        .build();
+    let _client = RiemannClient::new(&options);
+
+    for _ in 0..50 {
+        if server.connections() == 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(4, server.connections());
+}
+
+#[tokio::test]
+async fn test_calls_spread_over_pool() {
+    for selection in &[PoolSelection::RoundRobin, PoolSelection::LeastLoaded] {
+        let server = MockServer::start().await;
+        let options = RiemannClientOptionsBuilder::default()
+            .port(server.port())
+            .pool_size(3_usize)
+            .pool_selection(*selection)
+            .build();
+        let client = Arc::new(RiemannClient::new(&options));
+
+        let sends = (0..30).map(|_| {
+            let client = client.clone();
+            async move {
+                client
+                    .send_events(vec![EventBuilder::new().build()])
+                    .await
+                    .unwrap()
+            }
+        });
+        join_all(sends).await;
+
+        assert_eq!(30, server.events());
+        assert_eq!(3, server.connections());
+    }
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use rustmann::{
+    EventBuilder, ReplicatedClient, ReplicationPolicy, RiemannClient, RiemannClientOptions,
+    RiemannClientOptionsBuilder,
+};
+
+use common::{unused_port, MockServer};
+
+fn options(port: u16) -> RiemannClientOptions {
+    RiemannClientOptionsBuilder::default()
+        .port(port)
+        .connect_timeout_ms(500_u64)
+        .build()
+}
+
+async fn replicated(policy: ReplicationPolicy) -> (ReplicatedClient, MockServer, MockServer) {
+    let primary = MockServer::start().await;
+    let secondary = MockServer::start().await;
+
+    let mut client = ReplicatedClient::new(
+        "primary",
+        RiemannClient::new(&options(primary.port())),
+        policy,
+    );
+    client.add_replica("secondary", RiemannClient::new(&options(secondary.port())));
+    client.add_replica("down", RiemannClient::new(&options(unused_port())));
+    (client, primary, secondary)
+}
+
+#[tokio::test]
+async fn test_quorum_and_any() {
+    for policy in &[ReplicationPolicy::Quorum, ReplicationPolicy::Any] {
+        let (client, primary, secondary) = replicated(*policy).await;
+        let report = client
+            .send_events(vec![EventBuilder::new().service("dual").build()])
+            .await
+            .unwrap();
+        assert!(!report.is_complete());
+        assert_eq!(2, *report.acknowledged());
+        assert_eq!(1, report.failures().len());
+        assert_eq!("down", report.failures()[0].replica());
+        assert_eq!(1, primary.events());
+        assert_eq!(1, secondary.events());
+    }
+}
+
+#[tokio::test]
+async fn test_all_reports_failed_replica() {
+    let (client, primary, _secondary) = replicated(ReplicationPolicy::All).await;
+    let err = client
+        .send_events(vec![EventBuilder::new().service("dual").build()])
+        .await
+        .unwrap_err();
+
+    assert_eq!(2, *err.acknowledged());
+    assert_eq!(3, *err.required());
+    assert_eq!(1, err.failures().len());
+    assert_eq!("down", err.failures()[0].replica());
+    // the healthy replicas still got the events
+    assert_eq!(1, primary.events());
+
+    let events = client.send_query("true").await.unwrap();
+    assert_eq!(1, events.len());
+    assert_eq!("primary", client.primary());
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use rustmann::protos::riemann::Msg;
+use rustmann::{
+    EventBuilder, RetryPolicy, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder,
+};
+
+use common::{serve, unused_port};
+
+type Received = Arc<Mutex<Vec<Msg>>>;
+
+/// A riemann server that drops its first connection as soon as it reads a
+/// request, and serves the next ones normally. Returns the port, the
+/// messages served and the number of connections accepted.
+async fn flaky_server() -> (u16, Received, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Received::default();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let recv = received.clone();
+    let conns = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            if conns.fetch_add(1, Ordering::SeqCst) == 0 {
+                let _ = socket.read_u32().await;
+                continue;
+            }
+            tokio::spawn(serve(socket, recv.clone()));
+        }
+    });
+    (port, received, connections)
+}
+
+/// A riemann server that fails every request, counting them.
+async fn failing_server() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    let count = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let count = count.clone();
+            tokio::spawn(async move {
+                while let Ok(len) = socket.read_u32().await {
+                    let mut buf = vec![0u8; len as usize];
+                    socket.read_exact(&mut buf).await.unwrap();
+                    count.fetch_add(1, Ordering::SeqCst);
+
+                    let response = Msg {
+                        ok: Some(false),
+                        error: Some("no".to_owned()),
+                        ..Default::default()
+                    };
+                    let mut out = (response.encoded_len() as u32).to_be_bytes().to_vec();
+                    response.encode(&mut out).unwrap();
+                    socket.write_all(&out).await.unwrap();
+                }
+            });
+        }
+    });
+    (port, requests)
+}
+
+#[tokio::test]
+async fn test_transient_failure_is_retried_over_a_new_connection() {
+    let (port, received, connections) = flaky_server().await;
+    let options = RiemannClientOptionsBuilder::default().port(port).build();
+    let client = RiemannClient::new(&options).with_retry_policy(RetryPolicy::new(3).backoff_ms(1));
+
+    client
+        .send_events(vec![EventBuilder::new().service("retried").build()])
+        .await
+        .unwrap();
+    assert_eq!(1, client.retries());
+    assert_eq!(2, connections.load(Ordering::SeqCst));
+    assert_eq!(1, received.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_server_error_is_never_retried() {
+    let (port, requests) = failing_server().await;
+    let options = RiemannClientOptionsBuilder::default().port(port).build();
+    let client = RiemannClient::new(&options)
+        .with_retry_policy(RetryPolicy::new(5).backoff_ms(1).retry_if(|_| true));
+
+    let r = client.send_events(vec![EventBuilder::new().build()]).await;
+    assert!(matches!(r, Err(RiemannClientError::Server { .. })));
+    let r = client.send_query("true").await;
+    assert!(matches!(r, Err(RiemannClientError::Server { .. })));
+    assert_eq!(0, client.retries());
+    assert_eq!(2, requests.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_retry_policy() {
+    let options = RiemannClientOptionsBuilder::default()
+        .port(unused_port())
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let client = RiemannClient::new(&options).with_retry_policy(RetryPolicy::new(3).backoff_ms(1));
+
+    let r = client.send_events(vec![EventBuilder::new().build()]).await;
+    assert!(matches!(r, Err(RiemannClientError::Connect { .. })));
+    assert_eq!(2, client.retries());
+
+    let client =
+        RiemannClient::new(&options).with_retry_policy(RetryPolicy::new(3).retry_if(|_| false));
+    let r = client.send_query("true").await;
+    assert!(r.is_err());
+    assert_eq!(0, client.retries());
+}
+
+#[test]
+fn test_default_policy_never_retries() {
+    assert_eq!(1, RetryPolicy::default().max_attempts());
+}
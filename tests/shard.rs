@@ -0,0 +1,83 @@
+mod common;
+
+use rustmann::{
+    EventBuilder, RiemannClient, RiemannClientOptions, RiemannClientOptionsBuilder, ShardField,
+    ShardedClient,
+};
+
+use common::MockServer;
+
+fn sharded(names: &[&str]) -> ShardedClient {
+    let mut client = ShardedClient::new(vec![ShardField::Host, ShardField::Service]);
+    for name in names {
+        client.add_shard(*name, RiemannClient::new(&RiemannClientOptions::default()));
+    }
+    client
+}
+
+#[test]
+fn test_routing_is_stable() {
+    let a = sharded(&["a", "b", "c"]);
+    let b = sharded(&["a", "b", "c"]);
+
+    for i in 0..100 {
+        let event = EventBuilder::new()
+            .host(format!("host{}", i))
+            .service("cpu")
+            .build();
+        assert_eq!(a.shard_for(&event), b.shard_for(&event));
+    }
+}
+
+#[test]
+fn test_small_rebalance_on_new_shard() {
+    let before = sharded(&["a", "b", "c"]);
+    let after = sharded(&["a", "b", "c", "d"]);
+
+    let mut moved = 0;
+    for i in 0..1000 {
+        let event = EventBuilder::new()
+            .host(format!("host{}", i))
+            .service("cpu")
+            .build();
+        let new_shard = after.shard_for(&event).unwrap();
+        if before.shard_for(&event).unwrap() != new_shard {
+            // keys only ever move to the new shard
+            assert_eq!("d", new_shard);
+            moved += 1;
+        }
+    }
+    assert!(moved > 100 && moved < 400, "moved {}", moved);
+}
+
+#[tokio::test]
+async fn test_send_per_shard() {
+    let server_a = MockServer::start().await;
+    let server_b = MockServer::start().await;
+
+    let mut client = ShardedClient::new(vec![ShardField::Service]);
+    for (name, server) in &[("a", &server_a), ("b", &server_b)] {
+        let options = RiemannClientOptionsBuilder::default()
+            .port(server.port())
+            .build();
+        client.add_shard(*name, RiemannClient::new(&options));
+    }
+
+    let events: Vec<_> = (0..50)
+        .map(|i| {
+            EventBuilder::new()
+                .service(format!("svc{}", i % 10))
+                .build()
+        })
+        .collect();
+    let expected_a = events
+        .iter()
+        .filter(|e| client.shard_for(e) == Some("a"))
+        .count();
+
+    let results = client.send_events(events).await;
+    assert!(results.iter().all(|r| r.result().is_ok()));
+    assert_eq!(50, results.iter().map(|r| *r.events()).sum::<usize>());
+    assert_eq!(expected_a, server_a.events());
+    assert_eq!(50 - expected_a, server_b.events());
+}
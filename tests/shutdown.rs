@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+use rustmann::{EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder};
+
+use common::MockServer;
+
+#[tokio::test]
+async fn test_close_waits_for_calls() {
+    let server = MockServer::start().await;
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .build();
+    let client = Arc::new(RiemannClient::new(&options));
+
+    let sends: Vec<_> = (0..10)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.send_events(vec![EventBuilder::new().build()]).await })
+        })
+        .collect();
+    tokio::task::yield_now().await;
+
+    let report = client.close().await;
+    assert!(report.is_complete());
+
+    let mut delivered = 0;
+    for send in sends {
+        match send.await.unwrap() {
+            Ok(()) => delivered += 1,
+            Err(RiemannClientError::Closed) => {}
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+    assert_eq!(delivered, server.events());
+
+    let result = client.send_events(vec![EventBuilder::new().build()]).await;
+    assert!(matches!(result, Err(RiemannClientError::Closed)));
+    assert!(matches!(
+        client.send_query("true").await,
+        Err(RiemannClientError::Closed)
+    ));
+}
+
+#[tokio::test]
+async fn test_close_shuts_down_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(listener.local_addr().unwrap().port())
+        .build();
+    let client = RiemannClient::new(&options);
+    client.connect().await.unwrap();
+    let (mut socket, _) = listener.accept().await.unwrap();
+
+    client.close().await;
+
+    // end of stream, the client closed its side
+    let mut buf = [0u8; 16];
+    assert_eq!(0, socket.read(&mut buf).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_shutdown_reports_abandoned_calls() {
+    // accepts connections and never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(listener.local_addr().unwrap().port())
+        .socket_timeout_ms(5000u64)
+        .build();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    let client = Arc::new(RiemannClient::new(&options));
+
+    let sender = client.clone();
+    let send = tokio::spawn(async move {
+        sender
+            .send_events(vec![
+                EventBuilder::new().build(),
+                EventBuilder::new().build(),
+            ])
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let report = client
+        .shutdown(Instant::now() + Duration::from_millis(100))
+        .await;
+    assert_eq!(1, *report.abandoned_calls());
+    assert_eq!(2, *report.abandoned_events());
+    assert!(!report.is_complete());
+    send.abort();
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rustmann::protos::riemann::Msg;
+use rustmann::{
+    EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder, Spool,
+    SpoolDropPolicy, SpoolOptions, SpoolOptionsBuilder,
+};
+
+use common::{unused_port, MockServer};
+
+fn spool_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustmann-spool-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn msg(service: &str) -> Msg {
+    Msg {
+        events: vec![EventBuilder::new().service(service).build()],
+        ..Default::default()
+    }
+}
+
+fn service(msg: &Msg) -> &str {
+    msg.events[0].service.as_deref().unwrap()
+}
+
+#[test]
+fn test_replay_in_order_across_restart() {
+    let dir = spool_dir("restart");
+    let options = SpoolOptionsBuilder::default().segment_bytes(64_u64).build();
+
+    let mut spool = Spool::open(&dir, &options).unwrap();
+    for i in 0..10 {
+        assert!(spool.append(&msg(&format!("s{}", i))).unwrap());
+    }
+    assert_eq!(10, spool.pending());
+
+    // deliver two, then "crash" before committing the third
+    for i in 0..3 {
+        let m = spool.peek().unwrap().unwrap();
+        assert_eq!(format!("s{}", i), service(&m));
+        if i < 2 {
+            spool.commit().unwrap();
+        }
+    }
+    drop(spool);
+
+    let mut spool = Spool::open(&dir, &options).unwrap();
+    assert_eq!(8, spool.pending());
+    for i in 2..10 {
+        let m = spool.peek().unwrap().unwrap();
+        assert_eq!(format!("s{}", i), service(&m));
+        spool.commit().unwrap();
+    }
+    assert!(spool.peek().unwrap().is_none());
+    assert_eq!(0, spool.size());
+}
+
+#[test]
+fn test_corruption_is_detected() {
+    let dir = spool_dir("corrupt");
+    let options = SpoolOptions::default();
+
+    let mut spool = Spool::open(&dir, &options).unwrap();
+    spool.append(&msg("a")).unwrap();
+    spool.append(&msg("b")).unwrap();
+    drop(spool);
+
+    let segment = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().map(|e| e == "seg").unwrap_or(false))
+        .unwrap();
+    let mut data = fs::read(&segment).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    fs::write(&segment, data).unwrap();
+
+    let mut spool = Spool::open(&dir, &options).unwrap();
+    assert_eq!(1, spool.corrupted());
+    assert_eq!(1, spool.pending());
+    assert_eq!("a", service(&spool.peek().unwrap().unwrap()));
+    spool.commit().unwrap();
+    assert!(spool.peek().unwrap().is_none());
+}
+
+#[test]
+fn test_drop_policies() {
+    let record_len = 8 + {
+        use prost::Message;
+        msg("x").encoded_len() as u64
+    };
+
+    let options = SpoolOptionsBuilder::default()
+        .max_bytes(record_len * 3)
+        .segment_bytes(record_len)
+        .drop_policy(SpoolDropPolicy::DropNewest)
+        .build();
+    let mut spool = Spool::open(spool_dir("newest"), &options).unwrap();
+    for _ in 0..3 {
+        assert!(spool.append(&msg("x")).unwrap());
+    }
+    assert!(!spool.append(&msg("y")).unwrap());
+    assert_eq!(1, spool.dropped());
+    assert_eq!(3, spool.pending());
+
+    let options = SpoolOptionsBuilder::default()
+        .max_bytes(record_len * 3)
+        .segment_bytes(record_len)
+        .drop_policy(SpoolDropPolicy::DropOldest)
+        .build();
+    let mut spool = Spool::open(spool_dir("oldest"), &options).unwrap();
+    for s in &["a", "b", "c", "d"] {
+        assert!(spool.append(&msg(s)).unwrap());
+    }
+    assert_eq!(1, spool.dropped());
+    assert_eq!(3, spool.pending());
+    assert_eq!("b", service(&spool.peek().unwrap().unwrap()));
+}
+
+#[tokio::test]
+async fn test_client_spools_while_unreachable() {
+    let port = unused_port();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(port)
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let spool = Spool::open(spool_dir("client"), &SpoolOptions::default()).unwrap();
+    let client = RiemannClient::new(&options).with_spool(spool);
+
+    client
+        .send_events(vec![EventBuilder::new().service("first").build()])
+        .await
+        .unwrap();
+    assert_eq!(Some(1), client.spooled());
+
+    let server = MockServer::start_on(port).await;
+    client
+        .send_events(vec![EventBuilder::new().service("second").build()])
+        .await
+        .unwrap();
+    assert_eq!(Some(0), client.spooled());
+
+    let received = server.received.lock().unwrap();
+    let services: Vec<&str> = received.iter().map(service).collect();
+    assert_eq!(vec!["first", "second"], services);
+}
+
+#[tokio::test]
+async fn test_spool_drains_without_further_sends() {
+    let port = unused_port();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(port)
+        .reconnect_backoff_ms(10_u64)
+        .reconnect_backoff_max_ms(50_u64)
+        .build();
+    let spool = Spool::open(spool_dir("quiet"), &SpoolOptions::default()).unwrap();
+    let client = RiemannClient::new(&options).with_spool(spool);
+
+    client
+        .send_events(vec![EventBuilder::new().service("spooled").build()])
+        .await
+        .unwrap();
+    assert_eq!(Some(1), client.spooled());
+
+    let server = MockServer::start_on(port).await;
+    for _ in 0..100 {
+        if client.spooled() == Some(0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(Some(0), client.spooled());
+    assert_eq!(1, server.events());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_invalid_options_are_not_spooled() {
+    // unix sockets do not go with UDP, which `build` leaves for connect to
+    // report
+    let options = RiemannClientOptionsBuilder::default()
+        .unix_socket_path("/run/riemann.sock")
+        .use_udp(true)
+        .build();
+    let spool = Spool::open(spool_dir("invalid"), &SpoolOptions::default()).unwrap();
+    let client = RiemannClient::new(&options).with_spool(spool);
+
+    let r = client.send_events(vec![EventBuilder::new().build()]).await;
+    assert!(matches!(r, Err(RiemannClientError::InvalidOptions(_))));
+    assert_eq!(Some(0), client.spooled());
+}
@@ -0,0 +1,177 @@
+#![cfg(feature = "websocket")]
+
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+use rustmann::{Operation, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder};
+
+/// A websocket server that sends `batches[n]` on the n-th connection, then
+/// closes it.
+// the handshake callback signature is fixed by tungstenite
+#[allow(clippy::result_large_err)]
+async fn ws_server(batches: Vec<Vec<&'static str>>) -> (u16, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let uris = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = uris.clone();
+    tokio::spawn(async move {
+        for batch in batches {
+            let (socket, _) = listener.accept().await.unwrap();
+            let seen = seen.clone();
+            let mut ws = tokio_tungstenite::accept_hdr_async(socket, |req: &Request, resp| {
+                seen.lock().unwrap().push(req.uri().to_string());
+                Ok::<Response, _>(resp)
+            })
+            .await
+            .unwrap();
+            for event in batch {
+                ws.send(Message::text(event)).await.unwrap();
+            }
+            ws.close(None).await.unwrap();
+        }
+    });
+
+    (port, uris)
+}
+
+#[tokio::test]
+async fn test_subscribe_and_resubscribe() {
+    let (port, uris) = ws_server(vec![
+        vec![
+            r#"{"host":"h1","service":"cpu","state":"ok","metric":0.5,"tags":["a"],"time":"2020-01-01T00:00:01.5Z","ttl":60,"rack":"r1"}"#,
+            r#"{"host":"h1","service":"mem","metric":3}"#,
+        ],
+        vec![r#"{"host":"h2","service":"cpu","description":null}"#],
+    ])
+    .await;
+
+    let options = RiemannClientOptionsBuilder::default()
+        .ws_port(port)
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    let mut subscription = client.subscribe("service = \"cpu\"");
+
+    let cpu = subscription.next().await.unwrap().unwrap();
+    assert_eq!(Some("h1"), cpu.host.as_deref());
+    assert_eq!(Some(0.5), cpu.metric_d);
+    assert_eq!(vec!["a".to_owned()], cpu.tags);
+    assert_eq!(Some(1_577_836_801), cpu.time);
+    assert_eq!(Some(1_577_836_801_500_000), cpu.time_micros);
+    assert_eq!(Some(60.0), cpu.ttl);
+    assert_eq!("rack", cpu.attributes[0].key);
+    assert_eq!(Some("r1"), cpu.attributes[0].value.as_deref());
+
+    let mem = subscription.next().await.unwrap().unwrap();
+    assert_eq!(Some(3), mem.metric_sint64);
+
+    // the server closed the first connection
+    let next = subscription.next().await.unwrap().unwrap();
+    assert_eq!(Some("h2"), next.host.as_deref());
+    assert_eq!(None, next.description);
+
+    let uris = uris.lock().unwrap();
+    assert_eq!(2, uris.len());
+    assert_eq!(
+        "/index?subscribe=true&query=service%20%3D%20%22cpu%22",
+        uris[0]
+    );
+}
+
+#[tokio::test]
+async fn test_subscription_reports_connection_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    let options = RiemannClientOptionsBuilder::default()
+        .ws_port(port)
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    let mut subscription = client.subscribe("true");
+
+    assert!(subscription.next().await.unwrap().is_err());
+    assert!(subscription.next().await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn test_subscription_error_kinds() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        ws.send(Message::text("not json")).await.unwrap();
+        // dropped without a close handshake
+    });
+
+    let options = RiemannClientOptionsBuilder::default()
+        .ws_port(port)
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    let mut subscription = client.subscribe("true");
+
+    match subscription.next().await.unwrap() {
+        Err(RiemannClientError::Protocol { ref endpoint, .. }) => {
+            assert!(endpoint.contains(&port.to_string()), "{}", endpoint)
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+    match subscription.next().await.unwrap() {
+        Err(RiemannClientError::ConnectionClosed { operation, .. }) => {
+            assert_eq!(Operation::Subscribe, operation)
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn test_subscribe_over_tls() {
+    use std::path::PathBuf;
+
+    use rustls_pki_types::pem::PemObject;
+    use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    let certs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/certs");
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from_pem_file(certs.join("server.pem")).unwrap()],
+            PrivateKeyDer::from_pem_file(certs.join("server.key")).unwrap(),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let socket = acceptor.accept(socket).await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        ws.send(Message::text(r#"{"service":"secure"}"#))
+            .await
+            .unwrap();
+        futures::future::pending::<()>().await
+    });
+
+    let options = RiemannClientOptionsBuilder::default()
+        .host("localhost")
+        .ws_port(port)
+        .use_tls(true)
+        .tls_ca_file(certs.join("ca.pem"))
+        .build();
+    let client = RiemannClient::new(&options);
+    let mut subscription = client.subscribe("true");
+
+    let event = subscription.next().await.unwrap().unwrap();
+    assert_eq!(Some("secure"), event.service.as_deref());
+}
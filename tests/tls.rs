@@ -0,0 +1,385 @@
+#![cfg(feature = "tls")]
+
+//! The certificates in `tests/certs` are signed by a throwaway CA, valid for
+//! a hundred years, and were made with openssl.
+
+mod common;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::{ParsedCertificate, WebPkiClientVerifier};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use rustmann::{
+    EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder, TlsPin,
+};
+
+fn cert_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/certs")
+        .join(name)
+}
+
+type Received = Arc<Mutex<Vec<rustmann::protos::riemann::Msg>>>;
+type Peers = Arc<Mutex<Vec<CertificateDer<'static>>>>;
+
+/// A TLS server that only accepts clients with a certificate from the test
+/// CA, and records the certificate of each client that connects.
+async fn mtls_server() -> (u16, Received, Peers) {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(cert_path("ca.pem")).unwrap())
+        .unwrap();
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .unwrap();
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(
+            vec![CertificateDer::from_pem_file(cert_path("server.pem")).unwrap()],
+            PrivateKeyDer::from_pem_file(cert_path("server.key")).unwrap(),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let peers = Arc::new(Mutex::new(Vec::new()));
+
+    let recv = received.clone();
+    let seen = peers.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            if let Ok(socket) = acceptor.accept(socket).await {
+                let cert = socket.get_ref().1.peer_certificates().unwrap()[0].clone();
+                seen.lock().unwrap().push(cert.into_owned());
+                tokio::spawn(common::serve(socket, recv.clone()));
+            }
+        }
+    });
+    (port, received, peers)
+}
+
+fn client_cert(name: &str) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(cert_path(name)).unwrap()
+}
+
+/// Copy a client certificate and key to `dir`, where the client loads them
+/// from.
+fn install_client_cert(dir: &Path, cert: &str, key: &str) {
+    fs::copy(cert_path(cert), dir.join("client.pem")).unwrap();
+    fs::copy(cert_path(key), dir.join("client.key")).unwrap();
+}
+
+fn rotating_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustmann-tls-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    install_client_cert(&dir, "client-ec.pem", "client-ec-pkcs8.key");
+    dir
+}
+
+async fn send(client: &RiemannClient) {
+    client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .unwrap();
+}
+
+fn mtls_options(port: u16) -> RiemannClientOptionsBuilder {
+    RiemannClientOptionsBuilder::default()
+        .host("localhost")
+        .port(port)
+        .use_tls(true)
+        .tls_ca_file(cert_path("ca.pem"))
+}
+
+#[tokio::test]
+async fn test_mutual_tls_with_each_key_format() {
+    let (port, received, _) = mtls_server().await;
+
+    for (cert, key) in &[
+        ("client-ec.pem", "client-ec-pkcs8.key"),
+        ("client-ec.pem", "client-ec-sec1.key"),
+        ("client-rsa.pem", "client-rsa.key"),
+    ] {
+        let options = mtls_options(port)
+            .tls_cert_file(cert_path(cert))
+            .tls_key_file(cert_path(key))
+            .try_build()
+            .unwrap();
+        let client = RiemannClient::new(&options);
+        client
+            .send_events(vec![EventBuilder::new().service(*key).build()])
+            .await
+            .unwrap();
+    }
+    assert_eq!(3, received.lock().unwrap().len());
+
+    // the server turns away clients without a certificate
+    let options = mtls_options(port).try_build().unwrap();
+    let client = RiemannClient::new(&options);
+    assert!(client
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_invalid_pem_files() {
+    let invalid = |builder: RiemannClientOptionsBuilder| match builder.try_build() {
+        Err(RiemannClientError::InvalidOptions(e)) => e,
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("options should be invalid"),
+    };
+
+    let e = invalid(mtls_options(5555).tls_ca_file(cert_path("missing.pem")));
+    assert!(e.contains("missing.pem"), "{}", e);
+
+    // a key is not a certificate
+    let e = invalid(mtls_options(5555).tls_ca_file(cert_path("server.key")));
+    assert!(e.contains("no certificate found"), "{}", e);
+
+    let e = invalid(
+        mtls_options(5555)
+            .tls_cert_file(cert_path("client-ec.pem"))
+            .tls_key_file(cert_path("client-ec.pem")),
+    );
+    assert!(e.contains("no usable private key"), "{}", e);
+
+    // the key does not belong to the certificate
+    let e = invalid(
+        mtls_options(5555)
+            .tls_cert_file(cert_path("client-ec.pem"))
+            .tls_key_file(cert_path("client-rsa.key")),
+    );
+    assert!(e.contains("client-rsa.key"), "{}", e);
+
+    let e = invalid(mtls_options(5555).tls_cert_file(cert_path("client-ec.pem")));
+    assert!(e.contains("must be set together"), "{}", e);
+
+    let e = invalid(RiemannClientOptionsBuilder::default().tls_ca_file(cert_path("ca.pem")));
+    assert!(e.contains("use_tls"), "{}", e);
+}
+
+#[tokio::test]
+async fn test_build_defers_tls_errors() {
+    let (port, received, _) = mtls_server().await;
+    let dir = rotating_dir("deferred");
+    fs::write(dir.join("client.key"), "garbage").unwrap();
+    let options = mtls_options(port)
+        .tls_cert_file(dir.join("client.pem"))
+        .tls_key_file(dir.join("client.key"))
+        .build();
+    assert!(options.tls_config().is_none());
+    let client = RiemannClient::new(&options);
+    match client.send_events(vec![EventBuilder::new().build()]).await {
+        Err(RiemannClientError::InvalidOptions(e)) => assert!(e.contains("client.key"), "{}", e),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // the files are loaded on the next connection attempt
+    install_client_cert(&dir, "client-ec.pem", "client-ec-pkcs8.key");
+    send(&client).await;
+    assert_eq!(1, received.lock().unwrap().len());
+
+    let options = RiemannClientOptionsBuilder::default()
+        .port(port)
+        .tls_ca_file(cert_path("ca.pem"))
+        .build();
+    let client = RiemannClient::new(&options);
+    match client.send_events(vec![EventBuilder::new().build()]).await {
+        Err(RiemannClientError::InvalidOptions(e)) => assert!(e.contains("use_tls"), "{}", e),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn test_reload_tls_recycles_connections() {
+    let (port, _, peers) = mtls_server().await;
+    let dir = rotating_dir("reload");
+    let options = mtls_options(port)
+        .tls_cert_file(dir.join("client.pem"))
+        .tls_key_file(dir.join("client.key"))
+        .build();
+    let client = RiemannClient::new(&options);
+
+    send(&client).await;
+    send(&client).await;
+    assert_eq!(vec![client_cert("client-ec.pem")], *peers.lock().unwrap());
+
+    // a broken file is refused and the current certificate stays in use
+    fs::write(dir.join("client.key"), "garbage").unwrap();
+    assert!(client.reload_tls().is_err());
+    send(&client).await;
+    assert_eq!(1, peers.lock().unwrap().len());
+
+    install_client_cert(&dir, "client-rsa.pem", "client-rsa.key");
+    client.reload_tls().unwrap();
+    send(&client).await;
+    assert_eq!(
+        vec![client_cert("client-ec.pem"), client_cert("client-rsa.pem")],
+        *peers.lock().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_tls_config_is_per_client() {
+    let (port, received, _) = mtls_server().await;
+    let options = mtls_options(port)
+        .tls_cert_file(cert_path("client-ec.pem"))
+        .tls_key_file(cert_path("client-ec-pkcs8.key"))
+        .build();
+    let trusting = RiemannClient::new(&options);
+    let distrusting = RiemannClient::new(&options);
+
+    let trusts_nothing = ClientConfig::builder()
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth();
+    distrusting
+        .set_tls_config(Arc::new(trusts_nothing))
+        .unwrap();
+    assert!(distrusting
+        .send_events(vec![EventBuilder::new().build()])
+        .await
+        .is_err());
+
+    send(&trusting).await;
+    assert_eq!(1, received.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_watch_tls_files() {
+    let (port, _, peers) = mtls_server().await;
+    let dir = rotating_dir("watch");
+    let options = mtls_options(port)
+        .tls_cert_file(dir.join("client.pem"))
+        .tls_key_file(dir.join("client.key"))
+        .tls_reload_interval_ms(20_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+    send(&client).await;
+
+    install_client_cert(&dir, "client-rsa.pem", "client-rsa.key");
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send(&client).await;
+        if peers.lock().unwrap().len() == 2 {
+            break;
+        }
+    }
+    assert_eq!(
+        vec![client_cert("client-ec.pem"), client_cert("client-rsa.pem")],
+        *peers.lock().unwrap()
+    );
+}
+
+fn client_cert_options(port: u16) -> RiemannClientOptionsBuilder {
+    RiemannClientOptionsBuilder::default()
+        .host("127.0.0.1")
+        .port(port)
+        .use_tls(true)
+        .tls_cert_file(cert_path("client-ec.pem"))
+        .tls_key_file(cert_path("client-ec-pkcs8.key"))
+}
+
+async fn try_send(options: RiemannClientOptionsBuilder) -> Result<(), RiemannClientError> {
+    let client = RiemannClient::new(&options.try_build()?);
+    client.send_events(vec![EventBuilder::new().build()]).await
+}
+
+fn hex(bytes: &[u8], separator: &str) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+#[tokio::test]
+async fn test_server_name_override() {
+    let (port, _, _) = mtls_server().await;
+    let options = || client_cert_options(port).tls_ca_file(cert_path("ca.pem"));
+
+    try_send(options().tls_server_name("localhost"))
+        .await
+        .unwrap();
+    // the certificate is not valid for that name
+    let e = try_send(options().tls_server_name("riemann.internal"))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, RiemannClientError::Tls { .. }), "{:?}", e);
+    assert!(!e.is_retryable());
+
+    match options().tls_server_name("not a name!").try_build() {
+        Err(RiemannClientError::InvalidOptions(e)) => assert!(e.contains("tls_server_name")),
+        _ => panic!("server name should be invalid"),
+    }
+}
+
+#[tokio::test]
+async fn test_certificate_pinning() {
+    let (port, _, _) = mtls_server().await;
+    let server_cert = CertificateDer::from_pem_file(cert_path("server.pem")).unwrap();
+    let cert_fp = Sha256::digest(server_cert.as_ref());
+    let spki = ParsedCertificate::try_from(&server_cert)
+        .unwrap()
+        .subject_public_key_info();
+    let spki_fp = Sha256::digest(spki.as_ref());
+
+    // the server certificate is not valid for this name, nor signed by a
+    // known CA, but it is pinned
+    let pinned = |pin: TlsPin| {
+        client_cert_options(port)
+            .tls_server_name("riemann.internal")
+            .tls_pin(pin)
+    };
+    try_send(pinned(
+        TlsPin::certificate_sha256(&hex(&cert_fp, ":")).unwrap(),
+    ))
+    .await
+    .unwrap();
+    try_send(pinned(
+        TlsPin::public_key_sha256(&hex(&spki_fp, "")).unwrap(),
+    ))
+    .await
+    .unwrap();
+    assert!(try_send(pinned(TlsPin::Certificate([0; 32])))
+        .await
+        .is_err());
+
+    assert!(TlsPin::certificate_sha256("abcd").is_err());
+    match pinned(TlsPin::PublicKey([0; 32]))
+        .tls_ca_file(cert_path("ca.pem"))
+        .try_build()
+    {
+        Err(RiemannClientError::InvalidOptions(e)) => assert!(e.contains("pins")),
+        _ => panic!("a CA and pins cannot be combined"),
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_cut_off_is_retryable() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        // hang up before the handshake is done
+        while let Ok((socket, _)) = listener.accept().await {
+            drop(socket);
+        }
+    });
+
+    let e = try_send(mtls_options(port)).await.unwrap_err();
+    assert!(matches!(e, RiemannClientError::Connect { .. }), "{:?}", e);
+    assert!(e.is_retryable());
+}
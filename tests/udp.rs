@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use prost::Message;
+use tokio::net::UdpSocket;
+
+use rustmann::protos::riemann::Msg;
+use rustmann::{EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder};
+
+async fn udp_client(max_datagram_size: usize) -> (UdpSocket, RiemannClient) {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.local_addr().unwrap().port())
+        .use_udp(true)
+        .udp_max_datagram_size(max_datagram_size)
+        .build();
+    (server, RiemannClient::new(&options))
+}
+
+#[tokio::test]
+async fn test_batch_split_over_datagrams() {
+    let (server, client) = udp_client(300).await;
+
+    let events = (0..20)
+        .map(|i| {
+            EventBuilder::new()
+                .service(format!("service-{}", i))
+                .description("x".repeat(40))
+                .build()
+        })
+        .collect();
+    client.send_events(events).await.unwrap();
+
+    let mut services = Vec::new();
+    let mut datagrams = 0;
+    let mut buf = vec![0u8; 65536];
+    while services.len() < 20 {
+        let len = tokio::time::timeout(Duration::from_secs(1), server.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(len <= 300);
+        datagrams += 1;
+
+        let msg = Msg::decode(&buf[..len]).unwrap();
+        services.extend(msg.events.into_iter().map(|e| e.service.unwrap()));
+    }
+
+    assert!(datagrams > 1);
+    let expected: Vec<String> = (0..20).map(|i| format!("service-{}", i)).collect();
+    assert_eq!(expected, services);
+}
+
+#[tokio::test]
+async fn test_oversized_event_rejected() {
+    let (server, client) = udp_client(100).await;
+
+    let events = vec![
+        EventBuilder::new().service("small").build(),
+        EventBuilder::new().description("x".repeat(200)).build(),
+    ];
+    match client.send_events(events).await {
+        Err(RiemannClientError::DatagramTooLarge { size, max }) => {
+            assert!(size > 200);
+            assert_eq!(100, max);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // nothing of the batch was sent
+    let mut buf = vec![0u8; 65536];
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), server.recv(&mut buf))
+            .await
+            .is_err()
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use tokio::net::UnixListener;
+
+use rustmann::{EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder};
+
+#[tokio::test]
+async fn test_unix_socket_transport() {
+    let path = std::env::temp_dir().join(format!("rustmann-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let options = RiemannClientOptionsBuilder::default()
+        .unix_socket_path(path.clone())
+        .reconnect_backoff_ms(1_u64)
+        .build();
+    let client = RiemannClient::new(&options);
+
+    // nothing listens yet
+    assert!(client
+        .send_events(vec![EventBuilder::new().service("lost").build()])
+        .await
+        .is_err());
+
+    let listener = UnixListener::bind(&path).unwrap();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let recv = received.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(common::serve(socket, recv.clone()));
+        }
+    });
+
+    client
+        .send_events(vec![EventBuilder::new().service("unix").build()])
+        .await
+        .unwrap();
+    let events = client.send_query("true").await.unwrap();
+    assert_eq!(1, events.len());
+    assert_eq!(Some("unix"), events[0].service.as_deref());
+    assert_eq!(1, received.lock().unwrap().len());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_unix_socket_conflicts() {
+    let unix = || RiemannClientOptionsBuilder::default().unix_socket_path("/run/riemann.sock");
+    let invalid = |builder: RiemannClientOptionsBuilder| match builder.try_build() {
+        Err(RiemannClientError::InvalidOptions(e)) => e,
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("options should be invalid"),
+    };
+
+    assert!(invalid(unix().use_udp(true)).contains("use_udp"));
+    #[cfg(feature = "tls")]
+    assert!(invalid(unix().use_tls(true)).contains("use_tls"));
+    assert!(invalid(unix().endpoint(("a", 5555)).endpoint(("b", 5555))).contains("endpoints"));
+    unix().endpoint(("a", 5555)).try_build().unwrap();
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use rustmann::{
+    EventBuilder, RiemannClient, RiemannClientError, RiemannClientOptionsBuilder, Validation,
+    MAX_DESCRIPTION_LEN,
+};
+
+use common::MockServer;
+
+#[test]
+fn test_validate() {
+    assert!(EventBuilder::new()
+        .service("ok")
+        .metric(1.0)
+        .ttl(0.0)
+        .add_tag("a")
+        .try_build()
+        .is_ok());
+
+    let error = EventBuilder::new()
+        .metric_d(f64::NAN)
+        .metric_f(f32::INFINITY)
+        .ttl(-1.0)
+        .description("x".repeat(MAX_DESCRIPTION_LEN + 1))
+        .add_tag("")
+        .add_attribute("", Some("v"))
+        .try_build()
+        .unwrap_err();
+    let fields: Vec<_> = error.fields().iter().map(|f| f.field().as_str()).collect();
+    assert_eq!(
+        vec![
+            "metric_d",
+            "metric_f",
+            "ttl",
+            "description",
+            "tags[0]",
+            "attributes[0].key"
+        ],
+        fields
+    );
+    assert!(error.to_string().contains("ttl is negative"));
+}
+
+#[test]
+fn test_sanitize() {
+    let mut event = EventBuilder::new()
+        .metric_sint64(3)
+        .metric_d(f64::NAN)
+        .ttl(f32::NAN)
+        .description("é".repeat(MAX_DESCRIPTION_LEN))
+        .add_tag("")
+        .add_tag("b")
+        .add_attribute("", Some("v"))
+        .add_attribute("k", Some("v"))
+        .build();
+    event.sanitize();
+
+    assert!(event.validate().is_ok());
+    assert_eq!(Some(3), event.metric_sint64);
+    assert_eq!(None, event.metric_d);
+    assert_eq!(None, event.ttl);
+    assert_eq!(MAX_DESCRIPTION_LEN, event.description.unwrap().len());
+    assert_eq!(vec!["b".to_owned()], event.tags);
+    assert_eq!(1, event.attributes.len());
+}
+
+#[tokio::test]
+async fn test_client_validation() {
+    let server = MockServer::start().await;
+    let invalid = || {
+        vec![
+            EventBuilder::new().build(),
+            EventBuilder::new().ttl(-5.0).build(),
+        ]
+    };
+
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .validation(Validation::Reject)
+        .build();
+    let client = RiemannClient::new(&options);
+    match client.send_events(invalid()).await {
+        Err(RiemannClientError::Validation { index, error }) => {
+            assert_eq!(1, index);
+            assert_eq!("ttl", error.fields()[0].field());
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(0, server.events());
+
+    let options = RiemannClientOptionsBuilder::default()
+        .port(server.port())
+        .validation(Validation::Sanitize)
+        .build();
+    let client = RiemannClient::new(&options);
+    client.send_events(invalid()).await.unwrap();
+    assert_eq!(2, server.events());
+    assert_eq!(None, server.received.lock().unwrap()[0].events[1].ttl);
+}